@@ -0,0 +1,296 @@
+//Evaluation of the `[target.'cfg(...)'.dependencies]` tables against a platform, the same way cargo
+//does it: the cfg values are queried from `rustc --print cfg`.
+use crate::error::Error;
+use std::env;
+use std::fmt;
+use std::iter::Peekable;
+use std::process::Command;
+use std::str::Chars;
+
+#[derive(Debug, PartialEq)]
+pub enum Cfg {
+    Name(String),
+    KeyPair(String, String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CfgExpr {
+    Not(Box<CfgExpr>),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Value(Cfg),
+}
+
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cfg::Name(name) => write!(f, "{}", name),
+            Cfg::KeyPair(key, value) => write!(f, "{}=\"{}\"", key, value),
+        }
+    }
+}
+
+impl Cfg {
+    //Parses a line of `rustc --print cfg` output
+    fn from_rustc_line(line: &str) -> Cfg {
+        match line.find('=') {
+            Some(i) => Cfg::KeyPair(
+                line[..i].to_string(),
+                line[i + 1..].trim_matches('"').to_string(),
+            ),
+            None => Cfg::Name(line.to_string()),
+        }
+    }
+}
+
+impl CfgExpr {
+    pub fn parse(input: &str) -> Result<CfgExpr, Error> {
+        let mut chars = input.chars().peekable();
+        let expr = parse_expr(&mut chars)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(expr),
+            Some(c) => Err(Error::Manifest(format!(
+                "unexpected character '{}' in cfg({})",
+                c, input
+            ))),
+        }
+    }
+
+    pub fn matches(&self, cfgs: &[Cfg]) -> bool {
+        match self {
+            CfgExpr::Not(e) => !e.matches(cfgs),
+            CfgExpr::All(e) => e.iter().all(|e| e.matches(cfgs)),
+            CfgExpr::Any(e) => e.iter().any(|e| e.matches(cfgs)),
+            CfgExpr::Value(cfg) => cfgs.contains(cfg),
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), Error> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        Some(c) => Err(Error::Manifest(format!(
+            "expected '{}' in cfg, found '{}'",
+            expected, c
+        ))),
+        None => Err(Error::Manifest(format!(
+            "expected '{}' in cfg, found end of input",
+            expected
+        ))),
+    }
+}
+
+fn parse_ident(chars: &mut Peekable<Chars>) -> Result<String, Error> {
+    skip_whitespace(chars);
+    let mut ident = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_alphanumeric() || c == '_' {
+            ident.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    if ident.is_empty() {
+        Err(Error::Manifest("expected an identifier in cfg".to_string()))
+    } else {
+        Ok(ident)
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, Error> {
+    expect(chars, '"')?;
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(string),
+            Some(c) => string.push(c),
+            None => return Err(Error::Manifest("unterminated string in cfg".to_string())),
+        }
+    }
+}
+
+//Parses a comma separated list of expressions, including the surrounding parentheses
+fn parse_list(chars: &mut Peekable<Chars>) -> Result<Vec<CfgExpr>, Error> {
+    expect(chars, '(')?;
+    let mut list = Vec::new();
+    loop {
+        skip_whitespace(chars);
+        if chars.peek() == Some(&')') {
+            chars.next();
+            return Ok(list);
+        }
+        list.push(parse_expr(chars)?);
+        skip_whitespace(chars);
+        match chars.next() {
+            Some(',') => (),
+            Some(')') => return Ok(list),
+            _ => return Err(Error::Manifest("expected ',' or ')' in cfg".to_string())),
+        }
+    }
+}
+
+fn parse_expr(chars: &mut Peekable<Chars>) -> Result<CfgExpr, Error> {
+    let ident = parse_ident(chars)?;
+    skip_whitespace(chars);
+    match (ident.as_str(), chars.peek()) {
+        ("all", Some('(')) => Ok(CfgExpr::All(parse_list(chars)?)),
+        ("any", Some('(')) => Ok(CfgExpr::Any(parse_list(chars)?)),
+        ("not", Some('(')) => {
+            let mut list = parse_list(chars)?;
+            if list.len() != 1 {
+                return Err(Error::Manifest(
+                    "not() in cfg takes exactly one argument".to_string(),
+                ));
+            }
+            Ok(CfgExpr::Not(Box::new(list.remove(0))))
+        }
+        (_, Some('=')) => {
+            chars.next();
+            Ok(CfgExpr::Value(Cfg::KeyPair(ident, parse_string(chars)?)))
+        }
+        _ => Ok(CfgExpr::Value(Cfg::Name(ident))),
+    }
+}
+
+//The platform dependencies are documented for
+pub struct Platform {
+    pub triple: String,
+    pub cfgs: Vec<Cfg>,
+}
+
+impl Platform {
+    //Asks rustc for the cfg values of target, or the host if target is None. With a toolchain,
+    //the rustc of that toolchain is asked, like the cargo the docs are built with.
+    pub fn query(target: Option<&str>, toolchain: Option<&str>) -> Result<Platform, Error> {
+        let triple = match target {
+            Some(t) => t.to_string(),
+            None => rustc_output(&["-vV"], toolchain)?
+                .lines()
+                .find(|l| l.starts_with("host: "))
+                .map(|l| l["host: ".len()..].to_string())
+                .ok_or_else(|| {
+                    Error::Cargo("couldn't determine the host triple from `rustc -vV`".to_string())
+                })?,
+        };
+
+        let cfgs = rustc_output(&["--print", "cfg", "--target", &triple], toolchain)?
+            .lines()
+            .map(Cfg::from_rustc_line)
+            .collect();
+
+        Ok(Platform { triple, cfgs })
+    }
+
+    //Checks if a key of the `[target]` table, either a triple or a `cfg(...)` expression, applies
+    pub fn matches(&self, key: &str) -> Result<bool, Error> {
+        if key.starts_with("cfg(") && key.ends_with(')') {
+            Ok(CfgExpr::parse(&key[4..key.len() - 1])?.matches(&self.cfgs))
+        } else {
+            Ok(key == self.triple)
+        }
+    }
+}
+
+//The rustc to run: the one in `$RUSTC`, or `rustc +toolchain` through the rustup proxy if a
+//toolchain is requested
+fn rustc_command(toolchain: Option<&str>) -> Command {
+    match toolchain {
+        Some(toolchain) => {
+            let mut command = Command::new("rustc");
+            command.arg(format!("+{}", toolchain));
+            command
+        }
+        None => Command::new(env::var_os("RUSTC").unwrap_or_else(|| "rustc".into())),
+    }
+}
+
+pub(crate) fn rustc_output(args: &[&str], toolchain: Option<&str>) -> Result<String, Error> {
+    let output = rustc_command(toolchain)
+        .args(args)
+        .output()
+        .map_err(|e| Error::Cargo(format!("Couldn't run rustc: {}", e)))?;
+    if !output.status.success() {
+        let toolchain = toolchain.map(|t| format!("+{} ", t)).unwrap_or_default();
+        return Err(Error::Cargo(format!(
+            "`rustc {}{}` failed: {}",
+            toolchain,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cfg, CfgExpr, Platform};
+
+    fn linux() -> Platform {
+        Platform {
+            triple: "x86_64-unknown-linux-gnu".to_string(),
+            cfgs: ["unix", "target_os=\"linux\"", "target_pointer_width=\"64\""]
+                .iter()
+                .map(|l| Cfg::from_rustc_line(l))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_nested_cfg() {
+        let expr = CfgExpr::parse(r#"all(unix, not(target_os = "macos"))"#).unwrap();
+        assert_eq!(
+            expr,
+            CfgExpr::All(vec![
+                CfgExpr::Value(Cfg::Name("unix".to_string())),
+                CfgExpr::Not(Box::new(CfgExpr::Value(Cfg::KeyPair(
+                    "target_os".to_string(),
+                    "macos".to_string()
+                )))),
+            ])
+        );
+        assert!(CfgExpr::parse("all(unix").is_err());
+        assert!(CfgExpr::parse("not(unix, windows)").is_err());
+    }
+
+    #[test]
+    fn platform_matches_target_keys() {
+        let platform = linux();
+        assert!(platform.matches("cfg(unix)").unwrap());
+        assert!(platform.matches("x86_64-unknown-linux-gnu").unwrap());
+        assert!(platform
+            .matches(r#"cfg(any(windows, target_os = "linux"))"#)
+            .unwrap());
+        assert!(!platform.matches("cfg(windows)").unwrap());
+        assert!(!platform.matches("x86_64-pc-windows-msvc").unwrap());
+        assert!(!platform
+            .matches(r#"cfg(all(unix, target_pointer_width = "32"))"#)
+            .unwrap());
+    }
+
+    #[test]
+    fn rustc_for_toolchain() {
+        use super::rustc_command;
+        use std::env;
+        use std::ffi::OsStr;
+        let command = rustc_command(None);
+        assert_eq!(
+            command.get_program(),
+            env::var_os("RUSTC").unwrap_or_else(|| "rustc".into())
+        );
+        let command = rustc_command(Some("nightly"));
+        assert_eq!(command.get_program(), "rustc");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [OsStr::new("+nightly")]
+        );
+    }
+}
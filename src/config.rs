@@ -0,0 +1,312 @@
+//Defaults for the command line options. They are set per user in a config file and environment
+//variables, and per project in `[workspace.metadata.makedocs]` and `[package.metadata.makedocs]`.
+use crate::error::Error;
+use serde_derive::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProjectConfig {
+    //Like -e
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    //Like -i
+    #[serde(default)]
+    pub include: Vec<String>,
+    pub no_buildtime: Option<bool>,
+    pub root: Option<bool>,
+    pub document_private_items: Option<bool>,
+    pub open: Option<bool>,
+}
+
+//A `metadata` table of Cargo.toml, or of a package in the output of `cargo metadata`. Other tools'
+//settings are ignored.
+#[derive(Deserialize)]
+pub(crate) struct Metadata {
+    pub makedocs: Option<ProjectConfig>,
+}
+
+//Where settings come from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    //The user's config file
+    User(PathBuf),
+    //`[workspace.metadata.makedocs]` of a manifest
+    Workspace(PathBuf),
+    //`[package.metadata.makedocs]` of a manifest
+    Package(PathBuf),
+    //`CARGO_MAKEDOCS_*` environment variables
+    Environment,
+    CommandLine,
+}
+
+//Settings from every source, in the order they are layered
+#[derive(Debug, Default)]
+pub struct Config {
+    pub layers: Vec<(Source, ProjectConfig)>,
+}
+
+const ENV_PREFIX: &str = "CARGO_MAKEDOCS_";
+
+impl ProjectConfig {
+    //Reads the settings from `CARGO_MAKEDOCS_EXCLUDE`, `CARGO_MAKEDOCS_OPEN` and so on, using var
+    //to look the variables up. Lists are separated by commas or spaces.
+    pub fn from_env<F: Fn(&str) -> Option<String>>(var: F) -> Result<ProjectConfig, Error> {
+        let list = |name: &str| -> Vec<String> {
+            var(&format!("{}{}", ENV_PREFIX, name))
+                .iter()
+                .flat_map(|v| v.split([',', ' ']))
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let flag = |name: &str| -> Result<Option<bool>, Error> {
+            let name = format!("{}{}", ENV_PREFIX, name);
+            match var(&name).as_deref() {
+                None | Some("") => Ok(None),
+                Some("1") | Some("true") => Ok(Some(true)),
+                Some("0") | Some("false") => Ok(Some(false)),
+                Some(v) => Err(Error::Config(format!(
+                    "{} must be true or false, not {}",
+                    name, v
+                ))),
+            }
+        };
+        Ok(ProjectConfig {
+            exclude: list("EXCLUDE"),
+            include: list("INCLUDE"),
+            no_buildtime: flag("NO_BUILDTIME")?,
+            root: flag("ROOT")?,
+            document_private_items: flag("DOCUMENT_PRIVATE_ITEMS")?,
+            open: flag("OPEN")?,
+        })
+    }
+
+    //Layers other on top of self. The lists are combined, the other settings are overridden.
+    pub fn merge(mut self, other: ProjectConfig) -> ProjectConfig {
+        self.exclude.extend(other.exclude);
+        self.include.extend(other.include);
+        ProjectConfig {
+            exclude: self.exclude,
+            include: self.include,
+            no_buildtime: other.no_buildtime.or(self.no_buildtime),
+            root: other.root.or(self.root),
+            document_private_items: other.document_private_items.or(self.document_private_items),
+            open: other.open.or(self.open),
+        }
+    }
+}
+
+//`$XDG_CONFIG_HOME/cargo-makedocs/config.toml`, or `~/.config/cargo-makedocs/config.toml`
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("cargo-makedocs").join("config.toml"))
+}
+
+//Reads the user's config file, if there is one
+pub fn user_config() -> Result<Option<(Source, ProjectConfig)>, Error> {
+    let path = match user_config_path() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(Error::Io(
+                format!("Couldn't read {}", path.to_string_lossy()),
+                e,
+            ))
+        }
+    };
+    let config = toml::from_str(&contents)
+        .map_err(|e| Error::Config(format!("{} is invalid: {}", path.to_string_lossy(), e)))?;
+    Ok(Some((Source::User(path), config)))
+}
+
+impl Config {
+    //The settings of every layer combined, later layers taking precedence. The lists of a package
+    //layer are left out, they are applied to the dependencies of each package's own manifest.
+    pub fn merged(&self) -> ProjectConfig {
+        self.layers
+            .iter()
+            .fold(ProjectConfig::default(), |merged, (source, config)| {
+                let config = match source {
+                    Source::Package(_) => ProjectConfig {
+                        exclude: Vec::new(),
+                        include: Vec::new(),
+                        ..config.clone()
+                    },
+                    _ => config.clone(),
+                };
+                merged.merge(config)
+            })
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::User(path) => write!(f, "{}", path.to_string_lossy()),
+            Source::Workspace(path) => write!(
+                f,
+                "[workspace.metadata.makedocs] in {}",
+                path.to_string_lossy()
+            ),
+            Source::Package(path) => write!(
+                f,
+                "[package.metadata.makedocs] in {}",
+                path.to_string_lossy()
+            ),
+            Source::Environment => write!(f, "environment"),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+type ListSetting = fn(&ProjectConfig) -> &Vec<String>;
+type FlagSetting = fn(&ProjectConfig) -> Option<bool>;
+
+//Lists the effective settings, each with the source it comes from
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lists: [(&str, ListSetting); 2] =
+            [("exclude", |c| &c.exclude), ("include", |c| &c.include)];
+        for (key, list) in lists.iter() {
+            let mut empty = true;
+            for (source, config) in &self.layers {
+                for value in list(config) {
+                    writeln!(f, "{} = {:?} ({})", key, value, source)?;
+                    empty = false;
+                }
+            }
+            if empty {
+                writeln!(f, "{} = [] (default)", key)?;
+            }
+        }
+
+        let flags: [(&str, FlagSetting); 4] = [
+            ("no-buildtime", |c| c.no_buildtime),
+            ("root", |c| c.root),
+            ("document-private-items", |c| c.document_private_items),
+            ("open", |c| c.open),
+        ];
+        for (key, flag) in flags.iter() {
+            match self
+                .layers
+                .iter()
+                .rev()
+                .find_map(|(source, config)| Some((flag(config)?, source)))
+            {
+                Some((value, source)) => writeln!(f, "{} = {} ({})", key, value, source)?,
+                None => writeln!(f, "{} = false (default)", key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, ProjectConfig, Source};
+
+    #[test]
+    fn package_settings_override_workspace_settings() {
+        let workspace: ProjectConfig = toml::from_str(
+            r#"
+exclude = ["futures01"]
+no-buildtime = true
+open = true
+"#,
+        )
+        .unwrap();
+        let package: ProjectConfig = toml::from_str(
+            r#"
+exclude = ["winapi"]
+include = ["tokio-util"]
+open = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            workspace.merge(package),
+            ProjectConfig {
+                exclude: vec!["futures01".to_string(), "winapi".to_string()],
+                include: vec!["tokio-util".to_string()],
+                no_buildtime: Some(true),
+                root: None,
+                document_private_items: None,
+                open: Some(false),
+            }
+        );
+    }
+
+    #[test]
+    fn layered_settings_from_env() {
+        let env = |name: &str| match name {
+            "CARGO_MAKEDOCS_EXCLUDE" => Some("windows-sys, winapi".to_string()),
+            "CARGO_MAKEDOCS_OPEN" => Some("false".to_string()),
+            _ => None,
+        };
+        let user = ProjectConfig {
+            exclude: vec!["futures01".to_string()],
+            open: Some(true),
+            ..ProjectConfig::default()
+        };
+        let config = Config {
+            layers: vec![
+                (Source::User("/home/me/config.toml".into()), user),
+                (Source::Environment, ProjectConfig::from_env(env).unwrap()),
+            ],
+        };
+        let merged = config.merged();
+        assert_eq!(merged.exclude, ["futures01", "windows-sys", "winapi"]);
+        assert_eq!(merged.open, Some(false));
+        assert_eq!(
+            config.to_string(),
+            r#"exclude = "futures01" (/home/me/config.toml)
+exclude = "windows-sys" (environment)
+exclude = "winapi" (environment)
+include = [] (default)
+no-buildtime = false (default)
+root = false (default)
+document-private-items = false (default)
+open = false (environment)
+"#
+        );
+
+        let invalid = |_: &str| Some("yes please".to_string());
+        assert!(ProjectConfig::from_env(invalid).is_err());
+    }
+
+    #[test]
+    fn package_lists_stay_with_the_package() {
+        let package = ProjectConfig {
+            exclude: vec!["log".to_string()],
+            root: Some(true),
+            ..ProjectConfig::default()
+        };
+        let config = Config {
+            layers: vec![
+                (
+                    Source::Workspace("/ws/Cargo.toml".into()),
+                    ProjectConfig {
+                        exclude: vec!["futures01".to_string()],
+                        ..ProjectConfig::default()
+                    },
+                ),
+                (Source::Package("/ws/Cargo.toml".into()), package),
+            ],
+        };
+        let merged = config.merged();
+        assert_eq!(merged.exclude, ["futures01"]);
+        assert_eq!(merged.root, Some(true));
+    }
+}
@@ -0,0 +1,290 @@
+//Documents crates with the settings from their `[package.metadata.docs.rs]`, like docs.rs does.
+//The manifest of a crate is found through the resolver, or in cargo's registry and git caches.
+//Each crate with settings gets its own run in its own target dir, since `RUSTFLAGS` changes how
+//everything is built.
+use crate::cfg::rustc_output;
+use crate::{
+    cached_manifest_dir, doc_dir, package_name, separate_dir, DocCrate, Error, Operation, Reason,
+};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+//The docs.rs settings a crate is documented with
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocsRs {
+    //Features of the crate, as `key/feature` of the dependency declaring it
+    pub features: Vec<String>,
+    pub rustdoc_args: Vec<String>,
+    pub rustc_args: Vec<String>,
+}
+
+impl DocsRs {
+    //The flags as environment variables, added to the ones already set
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut vars = Vec::new();
+        for (var, args) in &[
+            ("RUSTDOCFLAGS", &self.rustdoc_args),
+            ("RUSTFLAGS", &self.rustc_args),
+        ] {
+            if args.is_empty() {
+                continue;
+            }
+            let mut flags: Vec<String> = env::var(var)
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect();
+            flags.extend(args.iter().cloned());
+            vars.push((*var, flags.join(" ")));
+        }
+        vars
+    }
+}
+
+//Reads the settings of the crate c from the manifest contents. None if it has none to apply, an
+//error message if they can't be applied.
+fn settings(c: &DocCrate, contents: &str) -> Result<Option<DocsRs>, String> {
+    let manifest: Value = toml::from_str(contents).map_err(|e| e.to_string())?;
+    let metadata = match manifest
+        .get("package")
+        .and_then(|p| p.get("metadata"))
+        .and_then(|m| m.get("docs"))
+        .and_then(|d| d.get("rs"))
+    {
+        Some(metadata) => metadata,
+        None => return Ok(None),
+    };
+    let strings = |key: &str| -> Result<Vec<String>, String> {
+        match metadata.get(key) {
+            Some(Value::Array(values)) => values
+                .iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or_else(|| format!("{} must be a list of strings", key)),
+            Some(_) => Err(format!("{} must be a list of strings", key)),
+            None => Ok(Vec::new()),
+        }
+    };
+    if !strings("cargo-args")?.is_empty() {
+        return Err("cargo-args isn't supported".to_string());
+    }
+
+    let mut features = strings("features")?;
+    if metadata.get("all-features").and_then(Value::as_bool) == Some(true) {
+        features.extend(all_features(&manifest));
+    }
+    if !features.is_empty() {
+        if let Reason::Transitive { .. } | Reason::Included = c.reason {
+            return Err("features can only be enabled for direct dependencies".to_string());
+        }
+        if let Some(f) = features.iter().find(|f| f.contains(['/', ':'])) {
+            return Err(format!(
+                "the feature {} can't be enabled through a dependency",
+                f
+            ));
+        }
+    }
+    let rustdoc_args = strings("rustdoc-args")?;
+    let rustc_args = strings("rustc-args")?;
+    if let Some(arg) = rustdoc_args
+        .iter()
+        .chain(&rustc_args)
+        .find(|a| a.contains(char::is_whitespace))
+    {
+        return Err(format!("the argument '{}' contains whitespace", arg));
+    }
+
+    if features.is_empty() && rustdoc_args.is_empty() && rustc_args.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(DocsRs {
+        features: features
+            .iter()
+            .map(|f| format!("{}/{}", c.key, f))
+            .collect(),
+        rustdoc_args,
+        rustc_args,
+    }))
+}
+
+//The features in `[features]` and the optional dependencies that aren't hidden behind `dep:`
+fn all_features(manifest: &Value) -> Vec<String> {
+    let table =
+        |value: Option<&Value>| value.and_then(Value::as_table).cloned().unwrap_or_default();
+    let declared = table(manifest.get("features"));
+    let mut features: Vec<String> = declared.keys().cloned().collect();
+    let hidden = |name: &str| {
+        declared
+            .values()
+            .flat_map(|v| v.as_array().into_iter().flatten())
+            .any(|v| v.as_str() == Some(format!("dep:{}", name).as_str()))
+    };
+    let targets = table(manifest.get("target"));
+    let dependencies = Some(manifest)
+        .into_iter()
+        .chain(targets.values())
+        .flat_map(|t| table(t.get("dependencies")));
+    for (name, dependency) in dependencies {
+        let optional = dependency.get("optional").and_then(Value::as_bool) == Some(true);
+        if optional && !hidden(&name) && !features.contains(&name) {
+            features.push(name);
+        }
+    }
+    features
+}
+
+//Whether the toolchain the docs are built with is a nightly, which docs.rs uses and most settings
+//like `--cfg docsrs` need for their unstable features
+pub(crate) fn is_nightly(toolchain: Option<&str>) -> Result<bool, Error> {
+    let version = rustc_output(&["-V"], toolchain)?;
+    Ok(version.contains("-nightly") || version.contains("-dev"))
+}
+
+//Finds the directory with the manifest of c
+fn manifest_dir(c: &DocCrate) -> Option<PathBuf> {
+    //A path dependency inherited from the workspace or patched is relative to another manifest
+    if let Some(ref dir) = c.manifest_dir {
+        return Some(dir.clone())
+            .filter(|dir| package_name(&dir.join("Cargo.toml")).as_deref() == Some(&c.name));
+    }
+    cached_manifest_dir(&c.name, c.version.as_ref()?, c.source.as_deref()?)
+}
+
+//Moves the crates with docs.rs settings out of operations into runs of their own, documented in
+//`makedocs/docs.rs/<key>` in target_dir, or another dir named by `separate_dir` if taken. Settings
+//that can't be applied are added to warnings.
+pub(crate) fn split_docs_rs_crates(
+    operations: Vec<Operation>,
+    target_dir: &Path,
+    target: Option<&str>,
+    used: &mut Vec<PathBuf>,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<Operation>, Error> {
+    let mut separate = Vec::new();
+    let mut kept = Vec::new();
+    for mut operation in operations {
+        let had_crates = !operation.crates.is_empty();
+        let first_separate = separate.len();
+        let mut crates = Vec::new();
+        for c in operation.crates {
+            let manifest_path = match manifest_dir(&c) {
+                Some(dir) => dir.join("Cargo.toml"),
+                None => {
+                    if c.source.is_some() {
+                        warnings.push(format!("couldn't find the manifest of {} to read its docs.rs settings, run `cargo fetch`", c.spec));
+                    }
+                    crates.push(c);
+                    continue;
+                }
+            };
+            let contents = fs::read_to_string(&manifest_path).map_err(|e| {
+                Error::Io(
+                    format!("Couldn't read {}", manifest_path.to_string_lossy()),
+                    e,
+                )
+            })?;
+            match settings(&c, &contents) {
+                Ok(Some(docs_rs)) => {
+                    let dir = separate_dir(&target_dir.join("makedocs").join("docs.rs"), &c, used);
+                    separate.push(Operation {
+                        dir: operation.dir.clone(),
+                        doc_dir: doc_dir(&dir, target),
+                        target_dir: Some(dir),
+                        crates: vec![c],
+                        excluded: Vec::new(),
+                        docs_rs: Some(docs_rs),
+                        features: operation.features.clone(),
+                    });
+                }
+                Ok(None) => crates.push(c),
+                Err(e) => {
+                    warnings.push(format!("the docs.rs settings of {} can't be applied, documenting it without them: {}", c.spec, e));
+                    crates.push(c);
+                }
+            }
+        }
+        operation.crates = crates;
+        if had_crates && operation.crates.is_empty() {
+            //Every crate got a run of its own
+            let moved_to: &mut Operation = &mut separate[first_separate];
+            moved_to.excluded.append(&mut operation.excluded);
+        } else {
+            kept.push(operation);
+        }
+    }
+    kept.extend(separate);
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn docs_rs_settings() {
+        use super::{settings, DocsRs};
+        use crate::{DependencyKind, DocCrate, Reason};
+        let dependency = |key: &str, reason| DocCrate {
+            key: key.to_string(),
+            name: "serde".to_string(),
+            spec: "serde:1.0.190".to_string(),
+            requirement: None,
+            version: None,
+            source: None,
+            manifest_dir: None,
+            reason,
+        };
+        let direct = || Reason::Dependency {
+            kind: DependencyKind::Normal,
+            target: None,
+        };
+        let manifest = r#"
+[package]
+name = "serde"
+[package.metadata.docs.rs]
+all-features = true
+rustdoc-args = ["--cfg", "docsrs"]
+[features]
+derive = ["serde_derive"]
+unstable = []
+[dependencies]
+serde_derive = { version = "1", optional = true }
+serde_json = { version = "1", optional = true }
+[target.'cfg(unix)'.dependencies]
+libc = { version = "0.2", optional = true }"#;
+        assert_eq!(
+            settings(&dependency("serde1", direct()), manifest),
+            Ok(Some(DocsRs {
+                features: vec![
+                    "serde1/derive".to_string(),
+                    "serde1/unstable".to_string(),
+                    "serde1/serde_derive".to_string(),
+                    "serde1/serde_json".to_string(),
+                    "serde1/libc".to_string(),
+                ],
+                rustdoc_args: vec!["--cfg".to_string(), "docsrs".to_string()],
+                rustc_args: vec![],
+            }))
+        );
+        assert!(settings(&dependency("serde", Reason::Included), manifest).is_err());
+
+        let rustdoc_only = "[package.metadata.docs.rs]\nrustdoc-args = [\"--cfg\", \"docsrs\"]";
+        assert!(
+            settings(&dependency("serde", Reason::Included), rustdoc_only)
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            settings(
+                &dependency("serde", direct()),
+                "[package]\nname = \"serde\""
+            ),
+            Ok(None)
+        );
+        assert!(settings(
+            &dependency("libc", direct()),
+            "[package.metadata.docs.rs]\ncargo-args = [\"-Zbuild-std=core\"]"
+        )
+        .is_err());
+    }
+}
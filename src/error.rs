@@ -0,0 +1,89 @@
+//The errors cargo-makedocs can fail with. Each kind of error exits with its own code, so scripts
+//can tell them apart.
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    //Cargo.toml is missing, invalid or describes something cargo-makedocs can't document
+    Manifest(String),
+    //Cargo.lock is missing or invalid
+    Lock(String),
+    //A version or version requirement semver can't parse
+    VersionReq(String),
+    //Reading a file or directory failed
+    Io(String, io::Error),
+    //Running cargo or rustc failed
+    Cargo(String),
+    //A setting is invalid, like a crate pattern or a value in the user's config file or a
+    //`CARGO_MAKEDOCS_*` environment variable
+    Config(String),
+    //`cargo doc` failed, and how many runs were skipped after the first failure with --fail-fast
+    DocFailed(Vec<FailedRun>, usize),
+}
+
+//A `cargo doc` run that exited unsuccessfully
+#[derive(Debug)]
+pub struct FailedRun {
+    pub dir: PathBuf,
+    //The specs of the crates the run documents, empty for the run opening the docs
+    pub crates: Vec<String>,
+    //The exit code, None if cargo was killed by a signal
+    pub code: Option<i32>,
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Manifest(_) => 2,
+            Error::Lock(_) => 3,
+            Error::VersionReq(_) => 4,
+            Error::Io(_, _) => 5,
+            Error::Cargo(_) => 6,
+            Error::Config(_) => 7,
+            Error::DocFailed(_, _) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Manifest(e)
+            | Error::Lock(e)
+            | Error::VersionReq(e)
+            | Error::Cargo(e)
+            | Error::Config(e) => write!(f, "{}", e),
+            Error::Io(context, e) => write!(f, "{}: {}", context, e),
+            Error::DocFailed(failed, skipped) => {
+                write!(f, "cargo doc failed in {} run(s):", failed.len())?;
+                for run in failed {
+                    write!(f, "\n  in {}", run.dir.to_string_lossy())?;
+                    if run.crates.is_empty() {
+                        write!(f, " opening the docs")?;
+                    } else {
+                        write!(f, " for {}", run.crates.join(", "))?;
+                    }
+                    match run.code {
+                        Some(code) => write!(f, " (exit code {})", code)?,
+                        None => write!(f, " (killed by a signal)")?,
+                    }
+                }
+                if *skipped > 0 {
+                    write!(f, "\n{} run(s) skipped because of --fail-fast", skipped)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
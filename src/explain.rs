@@ -0,0 +1,307 @@
+//Explains why a crate is or isn't documented, for `cargo makedocs explain`. The manifests are
+//read the same way `get_crates` reads them, and every declaration of the crate is followed
+//through the same steps.
+use crate::pattern::{self, CratePattern};
+use crate::{
+    enabled_optional_dependencies, is_optional, load_manifests, lock_candidates, plan,
+    resolve_dependency, CargoLock, CargoToml, DependencyKind, Error, LoadedManifest, Options,
+    Reason, ALL_KINDS,
+};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+//A declaration of the crate in a manifest
+#[derive(Debug)]
+pub struct Declaration {
+    pub manifest: PathBuf,
+    pub key: String,
+    //The table it is declared in, like `dependencies` or `target.'cfg(unix)'.dev-dependencies`
+    pub table: String,
+    pub requirement: Option<String>,
+    //The packages in Cargo.lock matching the requirement, the newest first
+    pub candidates: Vec<String>,
+    //The spec of the package that gets documented, None if Cargo.lock has no match
+    pub chosen: Option<String>,
+    //Why the declaration doesn't get the crate documented
+    pub skipped: Option<Skipped>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Skipped {
+    //The manifest belongs to a workspace member that isn't selected
+    MemberNotSelected,
+    //Dependencies of this kind aren't documented with the current options
+    Kind(DependencyKind),
+    //The `[target]` table doesn't apply to the platform with this triple
+    Platform(String),
+    //An optional dependency no active feature enables
+    Feature,
+    //Excluded by this pattern
+    Excluded(String),
+}
+
+#[derive(Debug)]
+pub struct Explanation {
+    pub name: String,
+    pub declarations: Vec<Declaration>,
+    //Each place the crate is documented: the directory, the spec passed to `cargo doc` and why
+    pub documented: Vec<(PathBuf, String, Reason)>,
+}
+
+fn table_name(kind: DependencyKind, target: Option<&str>) -> String {
+    let table = match kind {
+        DependencyKind::Normal => "dependencies",
+        DependencyKind::Build => "build-dependencies",
+        DependencyKind::Dev => "dev-dependencies",
+    };
+    match target {
+        Some(target) => format!("target.'{}'.{}", target, table),
+        None => table.to_string(),
+    }
+}
+
+//Follows every declaration of name in manifest
+fn declarations(
+    loaded: &LoadedManifest,
+    lock: &CargoLock,
+    options: &Options,
+    name: &str,
+) -> Result<Vec<Declaration>, Error> {
+    let manifest: &CargoToml = &loaded.manifest;
+    let mut tables: Vec<(DependencyKind, Option<&String>, &toml::value::Table)> = manifest
+        .dependencies
+        .tables(ALL_KINDS)
+        .map(|(kind, table)| (kind, None, table))
+        .collect();
+    for (target, dependencies) in manifest.target.iter().flatten() {
+        tables.extend(
+            dependencies
+                .tables(ALL_KINDS)
+                .map(|(kind, table)| (kind, Some(target), table)),
+        );
+    }
+
+    let enabled_optional = enabled_optional_dependencies(manifest, &options.features);
+    let config = manifest.package_config();
+    let excluded_crates: Vec<&str> = options
+        .excluded_crates
+        .iter()
+        .copied()
+        .chain(config.exclude.iter().map(String::as_str))
+        .collect();
+    let excluded_crates = CratePattern::parse_all(&excluded_crates)?;
+
+    let mut found = Vec::new();
+    for (kind, target, table) in tables {
+        for (k, v) in table {
+            let declared = manifest.declared(k, v)?;
+            if k != name && declared.name != name {
+                continue;
+            }
+            let candidates =
+                lock_candidates(lock, declared.name, declared.version, &declared.source)?
+                    .into_iter()
+                    .map(|(version, entry)| match entry.source {
+                        Some(ref source) => format!("{} {} ({})", entry.name, version, source),
+                        None => format!("{} {}", entry.name, version),
+                    })
+                    .collect();
+            let resolved = resolve_dependency(manifest, lock, &declared)?;
+
+            //The same checks as `get_crates`, in the same order
+            let enabled_kind = match kind {
+                DependencyKind::Normal => options.kinds.normal,
+                DependencyKind::Build => options.kinds.build,
+                DependencyKind::Dev => options.kinds.dev,
+            };
+            let names = [k.as_str(), declared.name];
+            let version = resolved.as_ref().map(|c| &c.version);
+            let skipped = if !loaded.selected {
+                Some(Skipped::MemberNotSelected)
+            } else if !enabled_kind {
+                Some(Skipped::Kind(kind))
+            } else if !target.map_or(Ok(true), |t| options.platform.matches(t))? {
+                Some(Skipped::Platform(options.platform.triple.clone()))
+            } else if is_optional(v) && !enabled_optional.contains(k) {
+                Some(Skipped::Feature)
+            } else {
+                pattern::matching(&excluded_crates, &names, version)
+                    .map(|p| Skipped::Excluded(p.text.to_string()))
+            };
+
+            found.push(Declaration {
+                manifest: loaded.dir.join("Cargo.toml"),
+                key: k.clone(),
+                table: table_name(kind, target.map(String::as_str)),
+                requirement: declared.requirement.map(str::to_string),
+                candidates,
+                chosen: resolved.map(|c| c.to_string()),
+                skipped,
+            });
+        }
+    }
+    Ok(found)
+}
+
+//Explains why the crate name is or isn't documented for the manifest at manifest_path. The
+//manifests are always read directly, like with `--resolver manifest`.
+pub fn explain(manifest_path: &Path, options: &Options, name: &str) -> Result<Explanation, Error> {
+    let manifest_dir = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+    let dir = manifest_dir.canonicalize().map_err(|e| {
+        Error::Io(
+            format!(
+                "Couldn't resolve the path {}",
+                manifest_dir.to_string_lossy()
+            ),
+            e,
+        )
+    })?;
+
+    let (lock, manifests) = load_manifests(dir, options, true)?;
+    let mut declarations_found = Vec::new();
+    for loaded in &manifests {
+        declarations_found.extend(declarations(loaded, &lock, options, name)?);
+    }
+
+    let documented = plan(manifest_path, options)?
+        .operations
+        .into_iter()
+        .flat_map(|o| {
+            let dir = o.dir;
+            o.crates
+                .into_iter()
+                .filter(|c| c.key == name || c.name == name)
+                .map(move |c| (dir.clone(), c.spec, c.reason))
+        })
+        .collect();
+
+    Ok(Explanation {
+        name: name.to_string(),
+        declarations: declarations_found,
+        documented,
+    })
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Skipped::MemberNotSelected => write!(
+                f,
+                "the workspace member isn't selected, see --workspace and --package"
+            ),
+            Skipped::Kind(DependencyKind::Normal) => {
+                write!(f, "only dev-dependencies are documented with --only-dev")
+            }
+            Skipped::Kind(DependencyKind::Build) => {
+                write!(f, "build-dependencies are ignored with --no-buildtime")
+            }
+            Skipped::Kind(DependencyKind::Dev) => {
+                write!(f, "dev-dependencies are only documented with --dev")
+            }
+            Skipped::Platform(triple) => write!(f, "the target doesn't apply to {}", triple),
+            Skipped::Feature => write!(f, "it is optional, and no active feature enables it"),
+            Skipped::Excluded(pattern) => write!(f, "excluded by the pattern {}", pattern),
+        }
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.documented.is_empty() {
+            writeln!(f, "{} is not documented", self.name)?;
+        } else {
+            writeln!(f, "{} is documented", self.name)?;
+            for (dir, spec, reason) in &self.documented {
+                writeln!(f, "  in {} as {}: {}", dir.to_string_lossy(), spec, reason)?;
+            }
+        }
+
+        if self.declarations.is_empty() {
+            return writeln!(f, "No manifest declares {}", self.name);
+        }
+        for d in &self.declarations {
+            write!(
+                f,
+                "{} declares {} in [{}]",
+                d.manifest.to_string_lossy(),
+                d.key,
+                d.table
+            )?;
+            match d.requirement {
+                Some(ref requirement) => writeln!(f, ", requirement {}", requirement)?,
+                None => writeln!(f)?,
+            }
+            if d.candidates.is_empty() {
+                writeln!(f, "  Cargo.lock has no matching package, run `cargo build`")?;
+            } else {
+                writeln!(f, "  Cargo.lock matches: {}", d.candidates.join(", "))?;
+            }
+            match d.chosen {
+                Some(ref chosen) => writeln!(f, "  resolves to {}", chosen)?,
+                None => writeln!(f, "  passed to cargo doc by name only")?,
+            }
+            if let Some(ref skipped) = d.skipped {
+                writeln!(f, "  skipped: {}", skipped)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn explain_declarations() {
+        use super::{declarations, Skipped};
+        use crate::tests::options;
+        use crate::{DependencyKind, LoadedManifest, Options};
+        use std::path::PathBuf;
+        let cargo_toml = r#"
+[dependencies]
+log = "0.4"
+rand = "0.7"
+[dev-dependencies]
+log = "0.4.8"
+[target.'cfg(windows)'.dependencies]
+rand = "0.8"
+"#;
+        let cargo_lock = r#"[[package]]
+name = "log"
+version = "0.4.6"
+[[package]]
+name = "log"
+version = "0.4.8"
+[[package]]
+name = "rand"
+version = "0.7.3""#;
+        let loaded = LoadedManifest {
+            dir: PathBuf::from("/app"),
+            manifest: toml::from_str(cargo_toml).unwrap(),
+            selected: true,
+        };
+        let lock = toml::from_str(cargo_lock).unwrap();
+        let options = Options {
+            excluded_crates: vec!["rand@0.7"],
+            ..options()
+        };
+
+        let log = declarations(&loaded, &lock, &options, "log").unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].table, "dependencies");
+        assert_eq!(log[0].candidates, ["log 0.4.8", "log 0.4.6"]);
+        assert_eq!(log[0].chosen.as_deref(), Some("log:0.4.8"));
+        assert_eq!(log[0].skipped, None);
+        assert_eq!(log[1].table, "dev-dependencies");
+        assert_eq!(log[1].skipped, Some(Skipped::Kind(DependencyKind::Dev)));
+
+        let rand = declarations(&loaded, &lock, &options, "rand").unwrap();
+        assert_eq!(
+            rand[0].skipped,
+            Some(Skipped::Excluded("rand@0.7".to_string()))
+        );
+        assert_eq!(rand[1].table, "target.'cfg(windows)'.dependencies");
+        assert!(rand[1].candidates.is_empty());
+        assert_eq!(rand[1].chosen, None);
+        assert!(matches!(rand[1].skipped, Some(Skipped::Platform(_))));
+    }
+}
@@ -0,0 +1,155 @@
+//The plan as JSON, for editors and scripts that need to know what gets documented and why
+use crate::{DependencyKind, DocCrate, DocPlan, ExcludedCrate, Operation, Reason};
+use serde_json::{json, Value};
+
+impl DocPlan {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "root": self.root,
+            "document_private_items": self.document_private_items,
+            "open": self.open,
+            "features": self.features,
+            "cargo_args": self.cargo_args,
+            "target": self.target,
+            "target_dir": self.target_dir,
+            "profile": self.profile,
+            "toolchain": self.toolchain,
+            "operations": self.operations.iter().map(operation).collect::<Vec<_>>(),
+            "warnings": self.warnings,
+        })
+    }
+}
+
+fn operation(operation: &Operation) -> Value {
+    json!({
+        "dir": operation.dir,
+        "target_dir": operation.target_dir,
+        "doc_dir": operation.doc_dir,
+        "features": operation.features,
+        "docs_rs": operation.docs_rs.as_ref().map(|d| json!({
+            "features": d.features,
+            "rustdoc_args": d.rustdoc_args,
+            "rustc_args": d.rustc_args,
+        })),
+        "crates": operation.crates.iter().map(doc_crate).collect::<Vec<_>>(),
+        "excluded": operation.excluded.iter().map(excluded_crate).collect::<Vec<_>>(),
+    })
+}
+
+fn doc_crate(c: &DocCrate) -> Value {
+    let (kind, target, parent, depth) = match c.reason {
+        Reason::Dependency { kind, ref target } => {
+            let kind = match kind {
+                DependencyKind::Normal => "normal",
+                DependencyKind::Build => "build",
+                DependencyKind::Dev => "dev",
+            };
+            (kind, target.as_deref(), None, None)
+        }
+        Reason::Included => ("include", None, None, None),
+        Reason::Transitive { ref parent, depth } => {
+            ("transitive", None, Some(parent.as_str()), Some(depth))
+        }
+    };
+    json!({
+        "key": c.key,
+        "name": c.name,
+        "spec": c.spec,
+        "requirement": c.requirement,
+        "version": c.version.as_ref().map(ToString::to_string),
+        "source": c.source,
+        "kind": kind,
+        "target": target,
+        "parent": parent,
+        "depth": depth,
+    })
+}
+
+fn excluded_crate(c: &ExcludedCrate) -> Value {
+    json!({
+        "key": c.key,
+        "name": c.name,
+        "version": c.version.as_ref().map(ToString::to_string),
+        "pattern": c.pattern,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn plan_to_json() {
+        use crate::{DocCrate, DocPlan, ExcludedCrate, Operation, Reason};
+        use semver::Version;
+        use serde_json::json;
+        use std::path::PathBuf;
+        let plan = DocPlan {
+            operations: vec![Operation {
+                dir: PathBuf::from("/app"),
+                crates: vec![DocCrate {
+                    key: "log".to_string(),
+                    name: "log".to_string(),
+                    spec: "log:0.4.8".to_string(),
+                    requirement: None,
+                    version: Some(Version::parse("0.4.8").unwrap()),
+                    source: Some(
+                        "registry+https://github.com/rust-lang/crates.io-index".to_string(),
+                    ),
+                    manifest_dir: None,
+                    reason: Reason::Transitive {
+                        parent: "env_logger".to_string(),
+                        depth: 1,
+                    },
+                }],
+                target_dir: None,
+                doc_dir: PathBuf::from("/app/target/doc"),
+                docs_rs: None,
+                features: vec![],
+                excluded: vec![ExcludedCrate {
+                    key: "futures01".to_string(),
+                    name: "futures".to_string(),
+                    version: None,
+                    pattern: "futures*".to_string(),
+                }],
+            }],
+            root: false,
+            document_private_items: false,
+            open: false,
+            features: vec![],
+            cargo_args: vec![],
+            target: None,
+            target_dir: None,
+            profile: None,
+            toolchain: None,
+            fail_fast: false,
+            warnings: vec![],
+        };
+        assert_eq!(
+            plan.to_json()["operations"][0],
+            json!({
+                "dir": "/app",
+                "target_dir": null,
+                "doc_dir": "/app/target/doc",
+                "features": [],
+                "docs_rs": null,
+                "crates": [{
+                    "key": "log",
+                    "name": "log",
+                    "spec": "log:0.4.8",
+                    "requirement": null,
+                    "version": "0.4.8",
+                    "source": "registry+https://github.com/rust-lang/crates.io-index",
+                    "kind": "transitive",
+                    "target": null,
+                    "parent": "env_logger",
+                    "depth": 1,
+                }],
+                "excluded": [{
+                    "key": "futures01",
+                    "name": "futures",
+                    "version": null,
+                    "pattern": "futures*",
+                }],
+            })
+        );
+    }
+}
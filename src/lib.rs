@@ -0,0 +1,3174 @@
+//Finds the dependencies of a crate or workspace to document, and documents them with `cargo doc`.
+//The `cargo makedocs` command is a thin wrapper around `plan` and `execute`.
+pub mod cfg;
+pub mod config;
+pub mod docsrs;
+pub mod error;
+pub mod explain;
+mod json;
+mod metadata;
+mod pattern;
+
+use cfg::Platform;
+use config::{Config, ProjectConfig, Source};
+use docsrs::DocsRs;
+pub use error::{Error, FailedRun};
+use pattern::CratePattern;
+use semver::{Version, VersionReq};
+use serde_derive::Deserialize;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use toml::value::{self, Value};
+
+#[derive(Deserialize)]
+struct Workspace {
+    #[serde(default)]
+    members: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    //Members documented unless --workspace or --package is used
+    #[serde(rename = "default-members")]
+    default_members: Option<Vec<String>>,
+    //Dependencies members can inherit with `foo = { workspace = true }`
+    dependencies: Option<value::Table>,
+    metadata: Option<config::Metadata>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    metadata: Option<config::Metadata>,
+}
+
+#[derive(Deserialize)]
+struct DependencyTables {
+    dependencies: Option<value::Table>,
+    #[serde(rename = "build-dependencies")]
+    build_dependencies: Option<value::Table>,
+    #[serde(rename = "dev-dependencies")]
+    dev_dependencies: Option<value::Table>,
+}
+
+#[derive(Deserialize)]
+struct CargoToml {
+    package: Option<Package>,
+    #[serde(flatten)]
+    dependencies: DependencyTables,
+    //Keyed by either a target triple or a `cfg(...)` expression
+    target: Option<BTreeMap<String, DependencyTables>>,
+    features: Option<BTreeMap<String, Vec<String>>>,
+    workspace: Option<Workspace>,
+    //Keyed by `crates-io`, a registry name or a registry or git URL
+    patch: Option<BTreeMap<String, value::Table>>,
+    //Keyed by package ID specs
+    replace: Option<value::Table>,
+}
+
+#[derive(Deserialize)]
+struct CargoLock {
+    package: Vec<LockEntry>,
+}
+
+#[derive(Deserialize)]
+struct LockEntry {
+    name: String,
+    version: String,
+    //Not set for path dependencies
+    source: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+//Which dependency tables of a manifest get documented
+#[derive(Clone, Copy)]
+pub struct DependencyKinds {
+    pub normal: bool,
+    pub build: bool,
+    pub dev: bool,
+}
+
+//The dependency table a crate is declared in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DependencyKind {
+    Normal,
+    Build,
+    Dev,
+}
+
+const ALL_KINDS: DependencyKinds = DependencyKinds {
+    normal: true,
+    build: true,
+    dev: true,
+};
+
+//The feature flags cargo would be invoked with
+pub struct FeatureSelection<'a> {
+    pub features: Vec<&'a str>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+}
+
+//A workspace member that can be picked with --package or --exclude-member
+struct Member {
+    dir: PathBuf,
+    name: Option<String>,
+}
+
+//Which workspace members get documented, like cargo's --workspace, --package and --exclude
+pub struct MemberSelection<'a> {
+    pub workspace: bool,
+    pub packages: Vec<&'a str>,
+    pub excluded: Vec<&'a str>,
+}
+
+//How the dependencies are found
+#[derive(Clone, Copy, PartialEq)]
+pub enum Resolver {
+    //By reading Cargo.toml and Cargo.lock
+    Manifest,
+    //From the dependency graph resolved by `cargo metadata`
+    Metadata,
+}
+
+//Settings that apply to every manifest being documented
+pub struct Options<'a> {
+    pub excluded_crates: Vec<&'a str>,
+    pub extra_crates: Vec<&'a str>,
+    pub kinds: DependencyKinds,
+    pub platform: Platform,
+    pub features: FeatureSelection<'a>,
+    //How many levels of transitive dependencies to document
+    pub depth: usize,
+    pub members: MemberSelection<'a>,
+    pub resolver: Resolver,
+    //Also document the root crate, or every member when at the root of a workspace
+    pub root: bool,
+    pub document_private_items: bool,
+    pub open: bool,
+    //The patterns given with -e and -i, which get a warning when they match nothing. The ones from
+    //the settings don't, since those are shared by projects that don't have every crate.
+    pub checked_patterns: Vec<&'a str>,
+    //Fail instead of warning when a pattern of -e or -i matches nothing
+    pub strict: bool,
+    //Arguments given after `--`, passed on to every `cargo doc` run
+    pub cargo_args: Vec<&'a str>,
+    //The triple passed with --target. The dependencies are filtered for `platform` either way.
+    pub target: Option<&'a str>,
+    pub target_dir: Option<&'a Path>,
+    pub profile: Option<&'a str>,
+    //The rustup toolchain cargo is run with, like `nightly`
+    pub toolchain: Option<&'a str>,
+    //Document crates with their `[package.metadata.docs.rs]` settings
+    pub docs_rs: bool,
+    //Stop at the first failed `cargo doc` run instead of running the others
+    pub fail_fast: bool,
+}
+
+impl<'a> FeatureSelection<'a> {
+    fn cargo_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.features.is_empty() {
+            args.push("--features".to_string());
+            args.push(self.features.join(","));
+        }
+        if self.all_features {
+            args.push("--all-features".to_string());
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        args
+    }
+
+    //The flags for a run in the workspace member with the given manifest. Cargo refuses features
+    //the selected packages don't have, so the ones the member doesn't declare are left out.
+    fn member_cargo_args(&self, manifest: &CargoToml) -> Vec<String> {
+        FeatureSelection {
+            features: self
+                .features
+                .iter()
+                .copied()
+                .filter(|f| manifest.declares_feature(f))
+                .collect(),
+            all_features: self.all_features,
+            no_default_features: self.no_default_features,
+        }
+        .cargo_args()
+    }
+}
+
+impl DependencyTables {
+    fn tables(
+        &self,
+        kinds: DependencyKinds,
+    ) -> impl Iterator<Item = (DependencyKind, &value::Table)> {
+        vec![
+            (kinds.normal, DependencyKind::Normal, &self.dependencies),
+            (kinds.build, DependencyKind::Build, &self.build_dependencies),
+            (kinds.dev, DependencyKind::Dev, &self.dev_dependencies),
+        ]
+        .into_iter()
+        .filter(|(enabled, _, _)| *enabled)
+        .filter_map(|(_, kind, table)| Some((kind, table.as_ref()?)))
+    }
+
+    fn tables_mut(&mut self) -> impl Iterator<Item = &mut value::Table> {
+        vec![
+            &mut self.dependencies,
+            &mut self.build_dependencies,
+            &mut self.dev_dependencies,
+        ]
+        .into_iter()
+        .filter_map(Option::as_mut)
+    }
+}
+
+impl CargoToml {
+    //Every dependency of every kind, including the target specific ones
+    fn all_dependencies(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.dependencies
+            .tables(ALL_KINDS)
+            .chain(
+                self.target
+                    .iter()
+                    .flat_map(BTreeMap::values)
+                    .flat_map(|t| t.tables(ALL_KINDS)),
+            )
+            .flat_map(|(_, table)| table)
+    }
+
+    //Replaces every `foo = { workspace = true }` dependency with the workspace's declaration of it,
+    //merged with the keys set by this manifest.
+    fn inherit_workspace_dependencies(
+        &mut self,
+        workspace_dependencies: Option<&value::Table>,
+    ) -> Result<(), Error> {
+        let tables = self.dependencies.tables_mut().chain(
+            self.target
+                .iter_mut()
+                .flat_map(BTreeMap::values_mut)
+                .flat_map(DependencyTables::tables_mut),
+        );
+        for table in tables {
+            for (key, dependency) in table.iter_mut() {
+                if dependency.get("workspace").and_then(Value::as_bool) == Some(true) {
+                    *dependency = inherit_dependency(key, dependency, workspace_dependencies)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //The settings of `[package.metadata.makedocs]`
+    fn package_config(&self) -> ProjectConfig {
+        self.package
+            .as_ref()
+            .and_then(|p| p.metadata.as_ref())
+            .and_then(|m| m.makedocs.clone())
+            .unwrap_or_default()
+    }
+
+    //Whether feature can be enabled for this package: a feature it declares, an optional
+    //dependency, or a `<dependency>/<feature>` or `<package>/<feature>` of one of them
+    fn declares_feature(&self, feature: &str) -> bool {
+        match feature.split_once('/') {
+            Some((name, _)) => {
+                self.package.as_ref().is_some_and(|p| p.name == name)
+                    || self.all_dependencies().any(|(k, _)| k == name)
+            }
+            None => {
+                self.features
+                    .as_ref()
+                    .is_some_and(|f| f.contains_key(feature))
+                    || self
+                        .all_dependencies()
+                        .any(|(k, v)| k == feature && is_optional(v))
+            }
+        }
+    }
+
+    //The settings of `[workspace.metadata.makedocs]`
+    fn workspace_config(&self) -> ProjectConfig {
+        self.workspace
+            .as_ref()
+            .and_then(|w| w.metadata.as_ref())
+            .and_then(|m| m.makedocs.clone())
+            .unwrap_or_default()
+    }
+
+    //Cargo only applies the [patch] and [replace] sections of the workspace root
+    fn inherit_overrides(&mut self, root: &CargoToml) {
+        self.patch = root.patch.clone();
+        self.replace = root.replace.clone();
+    }
+
+    //The paths in [patch] and [replace] are relative to the root manifest. They are made absolute
+    //so that they can be used in package ID specs.
+    fn make_override_paths_absolute(&mut self, root_dir: &Path) {
+        let tables = self
+            .patch
+            .iter_mut()
+            .flat_map(BTreeMap::values_mut)
+            .chain(self.replace.iter_mut());
+        for table in tables {
+            for (_, dependency) in table.iter_mut() {
+                if let Some(Value::String(path)) = dependency.get_mut("path") {
+                    let absolute = root_dir.join(&path).to_string_lossy().into_owned();
+                    *path = absolute;
+                }
+            }
+        }
+    }
+
+    //The package names of the dependencies declared only in `[target]` tables that don't apply
+    //to platform. A table with a key that can't be evaluated counts as applying.
+    fn foreign_dependencies(&self, platform: &Platform) -> Vec<String> {
+        let names = |tables: &DependencyTables| -> Vec<String> {
+            tables
+                .tables(ALL_KINDS)
+                .flat_map(|(_, table)| table.iter())
+                .map(|(k, v)| {
+                    v.get("package")
+                        .and_then(Value::as_str)
+                        .unwrap_or(k)
+                        .to_string()
+                })
+                .collect()
+        };
+        let mut applying = names(&self.dependencies);
+        let mut foreign = Vec::new();
+        for (target, tables) in self.target.iter().flatten() {
+            if platform.matches(target).unwrap_or(true) {
+                applying.extend(names(tables));
+            } else {
+                foreign.extend(names(tables));
+            }
+        }
+        foreign.retain(|n| !applying.contains(n));
+        foreign
+    }
+
+    //Reads the declaration of the dependency k. When the dependency is patched, the patched source
+    //is the one that gets built.
+    fn declared<'a>(&'a self, k: &'a str, v: &'a Value) -> Result<Declared<'a>, Error> {
+        let mut changed_name = None;
+        let mut source = DependencySource::CratesIo;
+        //If multiple versions of a library is flying about we need to specify the correct version
+        let version = match v {
+            //If the dependency is added as [dependencies.<crate>], this needs to be handled
+            Value::Table(t) => {
+                source = DependencySource::from_table(t);
+                if let Some(name) = t.get("package") {
+                    //Package is renamed
+                    changed_name = Some(name.as_str().ok_or_else(|| {
+                        Error::Manifest(format!("package of dependency {} is not a string", k))
+                    })?);
+                }
+                if let Some(v) = t.get("version") {
+                    v.as_str().ok_or_else(|| {
+                        Error::Manifest(format!("version of dependency {} is not a string", k))
+                    })?
+                } else if t.get("path").is_some() || t.get("git").is_some() {
+                    "*" //Assume that the user is developing the dependency if using a path
+                        //and that if using git, wants the latest version available
+                } else {
+                    return Err(Error::Manifest(format!("dependency {} is invalid", k)));
+                }
+            }
+            Value::String(s) => s,
+            _ => {
+                return Err(Error::Manifest(format!(
+                    "couldn't parse Cargo.toml: invalid value in key {}",
+                    k
+                )))
+            }
+        };
+        let name = changed_name.unwrap_or(k);
+        Ok(Declared {
+            name,
+            version,
+            requirement: match v {
+                Value::Table(t) => t.get("version").and_then(Value::as_str),
+                _ => Some(version),
+            },
+            source: self.patched_source(name, v).unwrap_or(source),
+        })
+    }
+
+    //The source a dependency is patched to with [patch], if any
+    fn patched_source(&self, name: &str, dependency: &Value) -> Option<DependencySource<'_>> {
+        let get = |key| dependency.get(key).and_then(Value::as_str);
+        if get("path").is_some() {
+            return None;
+        }
+        let source = get("git")
+            .or_else(|| get("registry"))
+            .or_else(|| get("registry-index"))
+            .unwrap_or("crates-io");
+        let crates_io = source == "crates-io";
+        self.patch
+            .iter()
+            .flatten()
+            .filter(|(key, _)| {
+                *key == source
+                    || same_repository(key, source)
+                    || (crates_io
+                        && CRATES_IO_SOURCES
+                            .iter()
+                            .any(|s| same_repository(key, &pkgid_url(s))))
+            })
+            .flat_map(|(_, patches)| patches)
+            .find(|(key, patch)| {
+                patch.get("package").and_then(Value::as_str).unwrap_or(key) == name
+            })
+            .and_then(|(_, patch)| patch.as_table())
+            .map(DependencySource::from_table)
+    }
+
+    //The source a resolved crate is replaced with by [replace], if any
+    fn replaced_source(&self, name: &str, version: &Version) -> Option<DependencySource<'_>> {
+        self.replace
+            .iter()
+            .flatten()
+            .find(|(spec, _)| {
+                //Like `foo`, `foo:1.0.0`, `foo@1.0.0` or `https://github.com/rust-lang/crates.io-index#foo@1.0.0`
+                let spec = spec.rsplit('#').next().unwrap();
+                let mut parts = spec.splitn(2, [':', '@']);
+                parts.next() == Some(name) && parts.next().is_none_or(|v| *v == version.to_string())
+            })
+            .and_then(|(_, replacement)| replacement.as_table())
+            .map(DependencySource::from_table)
+    }
+}
+
+fn inherit_dependency(
+    key: &str,
+    local: &Value,
+    workspace_dependencies: Option<&value::Table>,
+) -> Result<Value, Error> {
+    let mut inherited = match workspace_dependencies.and_then(|w| w.get(key)) {
+        Some(Value::String(version)) => {
+            let mut table = value::Table::new();
+            table.insert("version".to_string(), Value::String(version.clone()));
+            table
+        }
+        Some(Value::Table(table)) => table.clone(),
+        Some(_) => {
+            return Err(Error::Manifest(format!(
+                "couldn't parse the workspace's Cargo.toml: invalid value in key {}",
+                key
+            )))
+        }
+        None => {
+            return Err(Error::Manifest(format!(
+                "dependency {} is inherited from the workspace, but [workspace.dependencies] doesn't declare it",
+                key
+            )))
+        }
+    };
+
+    for (k, v) in local.as_table().into_iter().flatten() {
+        match (k.as_str(), inherited.get_mut(k), v) {
+            ("workspace", _, _) => (),
+            //Features are additive, everything else is overridden by the member
+            ("features", Some(Value::Array(features)), Value::Array(local_features)) => {
+                features.extend(local_features.iter().cloned())
+            }
+            _ => {
+                inherited.insert(k.clone(), v.clone());
+            }
+        }
+    }
+    Ok(Value::Table(inherited))
+}
+
+const CRATES_IO_SOURCES: [&str; 2] = [
+    "registry+https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io/",
+];
+
+//A dependency as declared in a manifest
+struct Declared<'a> {
+    //The real package name
+    name: &'a str,
+    //The requirement to resolve the version with
+    version: &'a str,
+    //The requirement written in the manifest, if any
+    requirement: Option<&'a str>,
+    source: DependencySource<'a>,
+}
+
+//Where a dependency is declared to come from, to pick between lock entries with the same name
+enum DependencySource<'a> {
+    CratesIo,
+    Registry,
+    Git {
+        url: &'a str,
+        //branch, tag or rev, and its value
+        reference: Option<(&'a str, &'a str)>,
+    },
+    Path(&'a str),
+}
+
+impl<'a> DependencySource<'a> {
+    fn from_table(table: &'a value::Table) -> DependencySource<'a> {
+        if let Some(url) = table.get("git").and_then(Value::as_str) {
+            let reference = ["branch", "tag", "rev"]
+                .iter()
+                .find_map(|k| Some((*k, table.get(*k)?.as_str()?)));
+            DependencySource::Git { url, reference }
+        } else if let Some(path) = table.get("path").and_then(Value::as_str) {
+            DependencySource::Path(path)
+        } else if table.get("registry").is_some() || table.get("registry-index").is_some() {
+            DependencySource::Registry
+        } else {
+            DependencySource::CratesIo
+        }
+    }
+
+    //Checks a `source` from Cargo.lock against the declared source
+    fn matches(&self, source: Option<&str>) -> bool {
+        match (self, source) {
+            (DependencySource::Path(_), None) => true,
+            (DependencySource::CratesIo, Some(s)) => CRATES_IO_SOURCES.contains(&s),
+            (DependencySource::Registry, Some(s)) => {
+                (s.starts_with("registry+") || s.starts_with("sparse+"))
+                    && !CRATES_IO_SOURCES.contains(&s)
+            }
+            (DependencySource::Git { url, reference }, Some(s)) => match s.strip_prefix("git+") {
+                Some(git) => {
+                    //Cargo.lock records the reference as a query, e.g. `?branch=main`
+                    let query = git.split('#').next().unwrap().split('?').nth(1);
+                    same_repository(&pkgid_url(s), url)
+                        && reference.map(|(k, v)| format!("{}={}", k, v)).as_deref() == query
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+fn same_repository(a: &str, b: &str) -> bool {
+    let normalize = |url: &str| {
+        url.trim_end_matches('/')
+            .trim_end_matches(".git")
+            .to_ascii_lowercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+//Turns a `source` from Cargo.lock into the URL used in package ID specs
+fn pkgid_url(source: &str) -> String {
+    let url = source
+        .strip_prefix("registry+")
+        .or_else(|| source.strip_prefix("git+"))
+        .unwrap_or(source);
+    url.split(['?', '#']).next().unwrap().to_string()
+}
+
+impl CargoLock {
+    //Finds a package by name and, if given, version and source
+    fn find(&self, name: &str, version: Option<&str>, source: Option<&str>) -> Option<&LockEntry> {
+        self.package.iter().find(|p| {
+            p.name == name
+                && version.is_none_or(|v| p.version == v)
+                && source.is_none_or(|s| p.source.as_deref() == Some(s))
+        })
+    }
+
+    //`name:version` is ambiguous if the same version comes from several sources
+    fn is_ambiguous(&self, entry: &LockEntry) -> bool {
+        self.package
+            .iter()
+            .filter(|p| p.name == entry.name && p.version == entry.version)
+            .count()
+            > 1
+    }
+
+    fn resolved<'a>(&'a self, entry: &'a LockEntry) -> Result<Crate<'a>, Error> {
+        Ok(Crate {
+            name: &entry.name,
+            version: lock_version(entry)?,
+            source: entry
+                .source
+                .as_deref()
+                .filter(|_| self.is_ambiguous(entry))
+                .map(Cow::Borrowed),
+            lock_source: entry.source.as_deref(),
+            dependencies: &entry.dependencies,
+        })
+    }
+
+    //Walks the dependency graph from crates, returning the dependencies up to depth levels down
+    //that aren't in crates already. Cargo.lock lists the dependencies for every platform, so the
+    //manifest of each crate, found with manifest_of, tells which ones apply to platform.
+    fn transitive_dependencies<'a>(
+        &'a self,
+        crates: &[Crate<'a>],
+        depth: usize,
+        excluded_crates: &[CratePattern],
+        platform: &Platform,
+        manifest_of: &dyn Fn(&Crate) -> Option<CargoToml>,
+    ) -> Result<Vec<(Crate<'a>, Reason)>, Error> {
+        let foreign = |c: &Crate| {
+            manifest_of(c)
+                .map(|m| m.foreign_dependencies(platform))
+                .unwrap_or_default()
+        };
+        let mut found: Vec<(Crate, Reason)> = Vec::new();
+        //The dependencies of each crate on the current level, with the name of that crate and the
+        //dependencies that don't apply to the platform
+        let mut level: Vec<(&str, &[String], Vec<String>)> = crates
+            .iter()
+            .map(|c| (c.name, c.dependencies, foreign(c)))
+            .collect();
+        for current_depth in 1..=depth {
+            let mut next = Vec::new();
+            for (parent, dependencies, foreign_dependencies) in &level {
+                for dependency in dependencies.iter() {
+                    //Either "name", "name version" or "name version (source)"
+                    let mut parts = dependency.splitn(3, ' ');
+                    let (name, version) = (parts.next().unwrap(), parts.next());
+                    let source = parts.next().map(|s| s.trim_matches(['(', ')']));
+                    if foreign_dependencies.iter().any(|f| f == name) {
+                        continue;
+                    }
+                    let dependency = match self.find(name, version, source) {
+                        Some(d) => self.resolved(d)?,
+                        None => continue,
+                    };
+                    if pattern::matches_any(
+                        excluded_crates,
+                        &[dependency.name],
+                        Some(&dependency.version),
+                    ) {
+                        continue;
+                    }
+                    let spec = dependency.to_string();
+                    let known = crates.iter().chain(found.iter().map(|(c, _)| c));
+                    if !known.map(Crate::to_string).any(|s| s == spec) {
+                        next.push((
+                            dependency.name,
+                            dependency.dependencies,
+                            foreign(&dependency),
+                        ));
+                        let reason = Reason::Transitive {
+                            parent: parent.to_string(),
+                            depth: current_depth,
+                        };
+                        found.push((dependency, reason));
+                    }
+                }
+            }
+            level = next;
+        }
+        Ok(found)
+    }
+}
+
+fn lock_version(entry: &LockEntry) -> Result<Version, Error> {
+    Version::parse(&entry.version).map_err(|e| {
+        Error::Lock(format!(
+            "invalid version {} of {} in Cargo.lock: {}",
+            entry.version, entry.name, e
+        ))
+    })
+}
+
+//Why a crate is documented
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reason {
+    //Declared in a dependency table of the manifest, or in a `[target]` specific one
+    Dependency {
+        kind: DependencyKind,
+        target: Option<String>,
+    },
+    //Passed with -i
+    Included,
+    //A dependency of parent, depth levels below the manifest's own dependencies
+    Transitive {
+        parent: String,
+        depth: usize,
+    },
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Dependency { kind, target } => {
+                match kind {
+                    DependencyKind::Normal => write!(f, "dependency")?,
+                    DependencyKind::Build => write!(f, "build dependency")?,
+                    DependencyKind::Dev => write!(f, "dev-dependency")?,
+                }
+                match target {
+                    Some(target) => write!(f, " for {}", target),
+                    None => Ok(()),
+                }
+            }
+            Reason::Included => write!(f, "included with -i"),
+            Reason::Transitive { parent, depth } => write!(
+                f,
+                "dependency of {}, {} level(s) below the direct dependencies",
+                parent, depth
+            ),
+        }
+    }
+}
+
+//A crate selected for documentation
+#[derive(Debug)]
+pub struct DocCrate {
+    //The key of the dependency in Cargo.toml, which differs from the name if it is renamed
+    pub key: String,
+    pub name: String,
+    //Passed to `cargo doc -p`
+    pub spec: String,
+    //The version requirement the dependency is declared with
+    pub requirement: Option<String>,
+    //The version resolved from Cargo.lock, if it was found there
+    pub version: Option<Version>,
+    //The source of the resolved package, None for path dependencies
+    pub source: Option<String>,
+    //The directory of the package's manifest, if the resolver knows it
+    pub manifest_dir: Option<PathBuf>,
+    pub reason: Reason,
+}
+
+//A dependency left out by a pattern of -e
+#[derive(Debug)]
+pub struct ExcludedCrate {
+    pub key: String,
+    pub name: String,
+    pub version: Option<Version>,
+    pub pattern: String,
+}
+
+//The crates selected for documentation in one directory
+#[derive(Debug, Default)]
+struct Selection {
+    crates: Vec<DocCrate>,
+    excluded: Vec<ExcludedCrate>,
+    warnings: Vec<String>,
+}
+
+//A `cargo doc` run
+#[derive(Debug)]
+pub struct Operation {
+    pub dir: PathBuf,
+    pub crates: Vec<DocCrate>,
+    //Set when the crates have to be documented separately from the others
+    pub target_dir: Option<PathBuf>,
+    //Where cargo puts the generated docs
+    pub doc_dir: PathBuf,
+    //The dependencies excluded from the main run
+    pub excluded: Vec<ExcludedCrate>,
+    //The docs.rs settings the crate of this run is documented with
+    pub docs_rs: Option<DocsRs>,
+    //The feature flags of the run: the requested features its workspace member declares
+    pub features: Vec<String>,
+}
+
+impl Operation {
+    //The front page generated for c
+    pub fn doc_path(&self, c: &DocCrate) -> PathBuf {
+        self.doc_dir
+            .join(c.name.replace('-', "_"))
+            .join("index.html")
+    }
+}
+
+//The crates to document per directory, and the crates the patterns of -e and -i can refer to: the
+//packages in the dependency graph, and the keys dependencies are declared with
+struct Resolution {
+    operations: Vec<(PathBuf, Selection)>,
+    known_crates: Vec<(String, Option<Version>)>,
+}
+
+//Everything `execute` needs to document the selected crates
+#[derive(Debug)]
+pub struct DocPlan {
+    pub operations: Vec<Operation>,
+    pub root: bool,
+    pub document_private_items: bool,
+    pub open: bool,
+    //The requested feature flags. Each run gets the ones of its operation.
+    pub features: Vec<String>,
+    //Arguments passed on to every run as they are
+    pub cargo_args: Vec<String>,
+    pub target: Option<String>,
+    //The target dir given with --target-dir, made absolute
+    pub target_dir: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub toolchain: Option<String>,
+    pub fail_fast: bool,
+    //Problems found while planning, for the caller to report
+    pub warnings: Vec<String>,
+}
+
+impl DocCrate {
+    //A crate given by name only, like the ones passed with -i
+    fn included(name: &str, version: Option<Version>) -> DocCrate {
+        DocCrate {
+            key: name.to_string(),
+            name: name.to_string(),
+            spec: name.to_string(),
+            requirement: None,
+            version,
+            source: None,
+            manifest_dir: None,
+            reason: Reason::Included,
+        }
+    }
+}
+
+//A directory in parent for the separate run of c, named after its key. If another run already
+//uses that name, like another version of a transitive crate or the same renamed key in another
+//workspace member, the version is added, and a number after that.
+pub(crate) fn separate_dir(parent: &Path, c: &DocCrate, used: &mut Vec<PathBuf>) -> PathBuf {
+    let mut name = c.key.clone();
+    if let (true, Some(version)) = (used.contains(&parent.join(&name)), &c.version) {
+        name = format!("{}-{}", c.key, version);
+    }
+    let mut dir = parent.join(&name);
+    let mut n = 2;
+    while used.contains(&dir) {
+        dir = parent.join(format!("{}-{}", name, n));
+        n += 1;
+    }
+    used.push(dir.clone());
+    dir
+}
+
+//Cargo documents a package name only once per target dir, so crates whose package name is
+//already being documented are split off into runs with their own target dir, named after their
+//key. A dependency that isn't renamed is the one kept in the main run. The dirs taken by
+//separate runs are kept in used.
+fn split_duplicate_crates(
+    dir: PathBuf,
+    crates: Vec<DocCrate>,
+    target_dir: &Path,
+    target: Option<&str>,
+    used: &mut Vec<PathBuf>,
+) -> Vec<Operation> {
+    let kept: Vec<usize> = crates
+        .iter()
+        .filter_map(|c| {
+            crates
+                .iter()
+                .position(|o| o.name == c.name && o.key == o.name)
+                .or_else(|| crates.iter().position(|o| o.name == c.name))
+        })
+        .collect();
+
+    let mut main = Vec::new();
+    let mut operations = Vec::new();
+    for (i, c) in crates.into_iter().enumerate() {
+        if kept.contains(&i) {
+            main.push(c);
+        } else {
+            let separate = separate_dir(&target_dir.join("makedocs"), &c, used);
+            operations.push(Operation {
+                dir: dir.clone(),
+                doc_dir: doc_dir(&separate, target),
+                target_dir: Some(separate),
+                crates: vec![c],
+                excluded: Vec::new(),
+                docs_rs: None,
+                features: Vec::new(),
+            });
+        }
+    }
+    operations.insert(
+        0,
+        Operation {
+            dir,
+            crates: main,
+            target_dir: None,
+            doc_dir: doc_dir(target_dir, target),
+            excluded: Vec::new(),
+            docs_rs: None,
+            features: Vec::new(),
+        },
+    );
+    operations
+}
+
+#[derive(Debug)]
+struct Crate<'a> {
+    pub name: &'a str,
+    pub version: Version,
+    //Only set when needed to tell packages with the same name and version apart
+    pub source: Option<Cow<'a, str>>,
+    //The source in Cargo.lock
+    lock_source: Option<&'a str>,
+    dependencies: &'a [String],
+}
+
+impl<'a> Crate<'a> {
+    //Where the crate comes from, None for path dependencies
+    fn source(&self) -> Option<String> {
+        self.source
+            .as_deref()
+            .or(self.lock_source)
+            .map(str::to_string)
+    }
+}
+
+impl<'a> fmt::Display for Crate<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.source {
+            Some(ref source) => write!(f, "{}#{}@{}", pkgid_url(source), self.name, self.version),
+            None => write!(f, "{}:{}", self.name, self.version),
+        }
+    }
+}
+
+//The packages in Cargo.lock matching the version requirement, the newest first. If some of them
+//come from the source the dependency is declared with, only those.
+fn lock_candidates<'a>(
+    lock: &'a CargoLock,
+    name: &str,
+    version: &str,
+    source: &DependencySource,
+) -> Result<Vec<(Version, &'a LockEntry)>, Error> {
+    let mut out = Vec::new();
+    let crate_version = VersionReq::parse(version).map_err(|e| {
+        Error::VersionReq(format!(
+            "invalid version requirement {} for {}: {}",
+            version, name, e
+        ))
+    })?;
+    for p in lock.package.iter().filter(|x| x.name == name) {
+        //Push the matching version numbers onto out
+        let lock_version = lock_version(p)?;
+        if crate_version.matches(&lock_version) {
+            out.push((lock_version, p));
+        }
+    }
+
+    //Prefer the packages from the source the dependency is declared with. If none of them
+    //match, the source is most likely a registry or git URL written differently.
+    if out.iter().any(|(_, p)| source.matches(p.source.as_deref())) {
+        out.retain(|(_, p)| source.matches(p.source.as_deref()));
+    }
+
+    //Ensure we use the most up to date, compatible crate
+    out.sort_unstable_by(|x, y| y.0.cmp(&x.0));
+    Ok(out)
+}
+
+fn correct_version<'a>(
+    lock: &'a CargoLock,
+    name: &str,
+    version: &str,
+    source: &DependencySource,
+) -> Result<Option<Crate<'a>>, Error> {
+    let p = match lock_candidates(lock, name, version, source)?.first() {
+        Some((_, p)) => *p,
+        None => return Ok(None),
+    };
+    let mut resolved = lock.resolved(p)?;
+    //Path dependencies have no source in Cargo.lock, so one is needed when they override a
+    //crate with the same version. Only absolute paths, like those of overrides, can be used.
+    if let DependencySource::Path(path) = source {
+        if p.source.is_none() && lock.is_ambiguous(p) && Path::new(path).is_absolute() {
+            resolved.source = Some(Cow::Owned(format!("path+file://{}", path)));
+        }
+    }
+    Ok(Some(resolved))
+}
+
+//Finds the package in Cargo.lock that gets built for a dependency of manifest
+fn resolve_dependency<'a>(
+    manifest: &CargoToml,
+    lock: &'a CargoLock,
+    declared: &Declared,
+) -> Result<Option<Crate<'a>>, Error> {
+    //Get the compatible version from Cargo.lock to always build the correct version.
+    let name = declared.name;
+    let mut resolved = correct_version(lock, name, declared.version, &declared.source)?;
+    //A replaced crate keeps its version, but comes from another source
+    let replacement = resolved.as_ref().and_then(|c| {
+        Some((
+            manifest.replaced_source(name, &c.version)?,
+            c.version.clone(),
+        ))
+    });
+    if let Some((replacement, version)) = replacement {
+        let exact = format!("={}", version);
+        if let Some(replaced) = correct_version(lock, name, &exact, &replacement)? {
+            resolved = Some(replaced);
+        }
+    }
+    Ok(resolved)
+}
+
+fn is_optional(dependency: &Value) -> bool {
+    dependency.get("optional").and_then(Value::as_bool) == Some(true)
+}
+
+//Resolves the selected features the way cargo does, and returns the keys of the optional
+//dependencies they enable.
+fn enabled_optional_dependencies(
+    manifest: &CargoToml,
+    selection: &FeatureSelection,
+) -> Vec<String> {
+    let optional: Vec<&str> = manifest
+        .all_dependencies()
+        .filter(|(_, v)| is_optional(v))
+        .map(|(k, _)| k.as_str())
+        .collect();
+
+    if selection.all_features {
+        return optional.iter().map(|s| s.to_string()).collect();
+    }
+
+    let no_features = BTreeMap::new();
+    let features = manifest.features.as_ref().unwrap_or(&no_features);
+    //An optional dependency only gets an implicit feature if no feature refers to it as `dep:<name>`
+    let explicit: Vec<&str> = features
+        .values()
+        .flatten()
+        .filter_map(|f| f.strip_prefix("dep:"))
+        .collect();
+
+    let mut queue = selection.features.clone();
+    if !selection.no_default_features {
+        queue.push("default");
+    }
+    let mut visited = Vec::new();
+    let mut enabled = Vec::new();
+    while let Some(feature) = queue.pop() {
+        if visited.contains(&feature) {
+            continue;
+        }
+        visited.push(feature);
+
+        if let Some(dependency) = feature.strip_prefix("dep:") {
+            enabled.push(dependency);
+        } else if let Some(i) = feature.find('/') {
+            //`foo/bar` enables the dependency foo, `foo?/bar` only applies if foo is already enabled
+            let dependency = &feature[..i];
+            if !dependency.ends_with('?') {
+                enabled.push(dependency);
+            }
+        } else if let Some(values) = features.get(feature) {
+            queue.extend(values.iter().map(String::as_str));
+        } else if !explicit.contains(&feature) {
+            enabled.push(feature);
+        }
+    }
+
+    enabled
+        .into_iter()
+        .filter(|d| optional.contains(d))
+        .map(str::to_string)
+        .collect()
+}
+
+fn get_crates(
+    manifest: CargoToml,
+    manifest_lock: &CargoLock,
+    options: &Options,
+) -> Result<Selection, Error> {
+    //Only chain the tables of the requested dependency kinds, and the target specific tables
+    //that apply to the platform
+    let reason = |kind, target: Option<&String>| Reason::Dependency {
+        kind,
+        target: target.cloned(),
+    };
+    let mut tables: Vec<(Reason, &value::Table)> = manifest
+        .dependencies
+        .tables(options.kinds)
+        .map(|(kind, table)| (reason(kind, None), table))
+        .collect();
+    for (target, dependencies) in manifest.target.iter().flatten() {
+        if options.platform.matches(target)? {
+            tables.extend(
+                dependencies
+                    .tables(options.kinds)
+                    .map(|(kind, table)| (reason(kind, Some(target)), table)),
+            );
+        }
+    }
+    let enabled_optional = enabled_optional_dependencies(&manifest, &options.features);
+    //The package's own settings apply to its dependencies on top of the options
+    let config = manifest.package_config();
+    let excluded_crates: Vec<&str> = options
+        .excluded_crates
+        .iter()
+        .copied()
+        .chain(config.exclude.iter().map(String::as_str))
+        .collect();
+    let excluded_crates = CratePattern::parse_all(&excluded_crates)?;
+    let extra_crates: Vec<&str> = options
+        .extra_crates
+        .iter()
+        .copied()
+        .chain(config.include.iter().map(String::as_str))
+        .collect();
+    let extra_crates = CratePattern::parse_all(&extra_crates)?;
+
+    let mut crates: Vec<(DocCrate, Option<Crate>)> = Vec::new();
+    let mut excluded = Vec::new();
+    let mut warnings = Vec::new();
+    let dependencies = tables
+        .into_iter()
+        .flat_map(|(reason, table)| table.iter().map(move |d| (reason.clone(), d)));
+    for (reason, (k, v)) in dependencies {
+        //Optional dependencies are only built when a selected feature enables them
+        if is_optional(v) && !enabled_optional.contains(k) {
+            continue;
+        }
+        let declared = manifest.declared(k, v)?;
+        let name = declared.name;
+        let exclude = |pattern: &CratePattern, version: Option<Version>| ExcludedCrate {
+            key: k.clone(),
+            name: name.to_string(),
+            version,
+            pattern: pattern.text.to_string(),
+        };
+        //Exclusions match the key or the package name, and the version once it is resolved
+        if let Some(pattern) = pattern::matching(&excluded_crates, &[k, name], None) {
+            excluded.push(exclude(pattern, None));
+            continue;
+        }
+        let resolved = resolve_dependency(&manifest, manifest_lock, &declared)?;
+        let version = resolved.as_ref().map(|c| &c.version);
+        if let Some(pattern) = pattern::matching(&excluded_crates, &[k, name], version) {
+            excluded.push(exclude(pattern, version.cloned()));
+            continue;
+        }
+        let spec = match resolved {
+            Some(ref c) => c.to_string(),
+            //Can happen if you run cargo-makedocs before cargo build.
+            //Pass just the crate name to get cargo to add it
+            None => {
+                warnings.push(format!("Crate {} not found in Cargo.lock, please run `cargo build`. `cargo doc` might fail or doc the wrong version.", name));
+                name.to_string()
+            }
+        };
+        let doc_crate = DocCrate {
+            key: k.clone(),
+            name: name.to_string(),
+            spec,
+            requirement: declared.requirement.map(str::to_string),
+            version: resolved.as_ref().map(|c| c.version.clone()),
+            source: resolved.as_ref().and_then(Crate::source),
+            //Relative to the manifest, made absolute by `manifest_crate_operations`
+            manifest_dir: match declared.source {
+                DependencySource::Path(path) => Some(PathBuf::from(path)),
+                _ => None,
+            },
+            reason,
+        };
+        crates.push((doc_crate, resolved));
+    }
+    for pattern in &extra_crates {
+        //A plain name is passed to cargo as is
+        if let (Some(name), false) = (pattern.exact, pattern.has_version()) {
+            let found = match manifest_lock.find(name, None, None) {
+                Some(c) => Some(manifest_lock.resolved(c)?),
+                None => None,
+            };
+            let doc_crate = DocCrate {
+                source: found.as_ref().and_then(Crate::source),
+                ..DocCrate::included(name, found.as_ref().map(|c| c.version.clone()))
+            };
+            crates.push((doc_crate, found));
+            continue;
+        }
+
+        //Otherwise the latest version in Cargo.lock of each matching crate is documented
+        let mut names: Vec<&str> = manifest_lock
+            .package
+            .iter()
+            .map(|p| p.name.as_str())
+            .filter(|n| pattern.matches_name(n))
+            .collect();
+        names.dedup();
+        let mut found_any = false;
+        for &name in &names {
+            let requirement = pattern.requirement();
+            let found = correct_version(
+                manifest_lock,
+                name,
+                requirement,
+                &DependencySource::CratesIo,
+            )?;
+            if let Some(c) = found {
+                let doc_crate = DocCrate {
+                    spec: c.to_string(),
+                    requirement: pattern.version_requirement().map(str::to_string),
+                    source: c.source(),
+                    ..DocCrate::included(name, Some(c.version.clone()))
+                };
+                crates.push((doc_crate, Some(c)));
+                found_any = true;
+            }
+        }
+        //Crates that aren't in Cargo.lock at all are reported by `plan`
+        if let (Some(name), false, true) = (pattern.exact, found_any, names.is_empty()) {
+            crates.push((DocCrate::included(name, None), None));
+        }
+    }
+
+    //A crate can be listed both in the platform independent and a target specific table
+    let mut seen = Vec::new();
+    crates.retain(|(c, _)| {
+        if seen.contains(&c.spec) {
+            false
+        } else {
+            seen.push(c.spec.clone());
+            true
+        }
+    });
+
+    let (mut doc_crates, resolved): (Vec<DocCrate>, Vec<Option<Crate>>) =
+        crates.into_iter().unzip();
+    let resolved: Vec<Crate> = resolved.into_iter().flatten().collect();
+    let transitive = manifest_lock.transitive_dependencies(
+        &resolved,
+        options.depth,
+        &excluded_crates,
+        &options.platform,
+        &cached_manifest,
+    )?;
+    doc_crates.extend(transitive.into_iter().map(|(c, reason)| DocCrate {
+        key: c.name.to_string(),
+        name: c.name.to_string(),
+        spec: c.to_string(),
+        requirement: None,
+        version: Some(c.version.clone()),
+        source: c.source(),
+        manifest_dir: None,
+        reason,
+    }));
+    Ok(Selection {
+        crates: doc_crates,
+        excluded,
+        warnings,
+    })
+}
+
+fn create_arguments(input: &[DocCrate]) -> impl Iterator<Item = &str> {
+    input.iter().flat_map(|c| vec!["-p", &c.spec])
+}
+
+//The cargo to run: the one in `$CARGO`, which cargo sets when running a subcommand, or the one in
+//PATH. Only the rustup proxy understands `+toolchain`, so it is used when a toolchain is requested.
+pub(crate) fn cargo_command(toolchain: Option<&str>) -> Command {
+    match toolchain {
+        Some(toolchain) => {
+            let mut command = Command::new("cargo");
+            command.arg(format!("+{}", toolchain));
+            command
+        }
+        None => Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into())),
+    }
+}
+
+//Asks cargo for the package ID of the crate in dir
+fn root_package_id(dir: &Path, toolchain: Option<&str>) -> Result<String, Error> {
+    let output = cargo_command(toolchain)
+        .current_dir(dir)
+        .arg("pkgid")
+        .output()
+        .map_err(|e| Error::Cargo(format!("Couldn't run cargo pkgid: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::Cargo(format!(
+            "cargo pkgid failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).replace("\n", ""))
+}
+
+//Looks for Cargo.toml in the current directory and every directory above it.
+pub fn find_manifest() -> Result<PathBuf, Error> {
+    let dir = env::current_dir().map_err(|e| Error::Io("Can't find Cargo.toml".to_string(), e))?;
+    match dir.ancestors().find(|a| a.join("Cargo.toml").is_file()) {
+        Some(root) => Ok(root.join("Cargo.toml")),
+        None => Err(Error::Manifest(
+            "Cannot find Cargo.toml in any ancestor directory".to_string(),
+        )),
+    }
+}
+
+//Expands glob patterns in `members` or `default-members` like cargo does. Excluded directories,
+//and directories matched by a pattern that don't contain a manifest, are skipped.
+fn member_dirs(root: &Path, members: &[String], exclude: &[String]) -> Result<Vec<PathBuf>, Error> {
+    let excluded: Vec<PathBuf> = exclude.iter().map(|e| root.join(e)).collect();
+    let escaped_root = glob::Pattern::escape(&root.to_string_lossy());
+
+    let mut dirs = Vec::new();
+    for member in members {
+        let is_pattern = member.contains(['*', '?', '[']);
+        let matched = if is_pattern {
+            let pattern = Path::new(&escaped_root).join(member);
+            glob::glob(&pattern.to_string_lossy())
+                .map_err(|e| {
+                    Error::Manifest(format!("Invalid workspace member {}: {}", member, e))
+                })?
+                .filter_map(Result::ok)
+                .filter(|path| path.join("Cargo.toml").is_file())
+                .collect()
+        } else {
+            //Cargo doesn't care about the actual name of a workspaced crate, just it's path
+            vec![root.join(member)]
+        };
+
+        for dir in matched {
+            if !excluded.iter().any(|e| dir.starts_with(e)) && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+//Picks the directories of the workspace members to document. Members can be referred to by
+//package name or by path.
+fn select_members(
+    root: &Path,
+    all: &[Member],
+    default_members: Option<Vec<PathBuf>>,
+    selection: &MemberSelection,
+) -> Result<Vec<PathBuf>, Error> {
+    let matches = |member: &Member, spec: &str| {
+        root.join(spec) == member.dir || member.name.as_deref() == Some(spec)
+    };
+
+    let mut selected = if !selection.packages.is_empty() {
+        let mut selected = Vec::new();
+        for package in &selection.packages {
+            let matching: Vec<&Member> = all.iter().filter(|m| matches(m, package)).collect();
+            if matching.is_empty() {
+                return Err(Error::Manifest(format!(
+                    "package {} is not a member of the workspace",
+                    package
+                )));
+            }
+            for member in matching {
+                if !selected.contains(&member.dir) {
+                    selected.push(member.dir.clone());
+                }
+            }
+        }
+        selected
+    } else if selection.workspace || default_members.is_none() {
+        all.iter().map(|m| m.dir.clone()).collect()
+    } else {
+        default_members.unwrap()
+    };
+
+    selected.retain(|dir| {
+        !all.iter()
+            .any(|m| m.dir == *dir && selection.excluded.iter().any(|spec| matches(m, spec)))
+    });
+    Ok(selected)
+}
+
+fn read_manifest(dir: &Path) -> Result<CargoToml, Error> {
+    let path = dir.join("Cargo.toml");
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| Error::Io(format!("Couldn't read {}", path.to_string_lossy()), e))?;
+
+    toml::from_str(&contents)
+        .map_err(|e| Error::Manifest(format!("{:?} failed to parse: {}", dir, e)))
+}
+
+//The target dir cargo uses for dir: the one set with `CARGO_TARGET_DIR` or `build.target-dir` in
+//a `.cargo/config.toml`, or `target` at the root of its workspace. Like cargo, a relative path in
+//a config file is relative to the directory containing `.cargo`.
+fn target_dir(dir: &Path) -> Result<PathBuf, Error> {
+    for var in &["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"] {
+        match env::var_os(var) {
+            Some(target_dir) if !target_dir.is_empty() => return Ok(dir.join(target_dir)),
+            _ => (),
+        }
+    }
+
+    let config_dirs = dir
+        .ancestors()
+        .map(|a| a.join(".cargo"))
+        .chain(cargo_home());
+    for config_dir in config_dirs {
+        for name in &["config.toml", "config"] {
+            let path = config_dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| Error::Io(format!("Couldn't read {}", path.to_string_lossy()), e))?;
+            if let Some(target_dir) = configured_target_dir(&contents)
+                .map_err(|e| Error::Config(format!("{}: {}", path.to_string_lossy(), e)))?
+            {
+                let base = config_dir.parent().unwrap_or(&config_dir);
+                return Ok(base.join(target_dir));
+            }
+            //Cargo ignores `config` when there is a `config.toml`
+            break;
+        }
+    }
+
+    Ok(dir
+        .ancestors()
+        .find(|a| {
+            a.join("Cargo.toml").is_file() && read_manifest(a).is_ok_and(|m| m.workspace.is_some())
+        })
+        .unwrap_or(dir)
+        .join("target"))
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".cargo")))
+}
+
+//Finds the unpacked package in cargo's registry or git caches
+fn cached_manifest_dir(name: &str, version: &Version, source: &str) -> Option<PathBuf> {
+    let cargo_home = cargo_home()?;
+    if source.starts_with("git+") {
+        //Checkouts are in `<repository>-<hash>/<short commit>`, anywhere in the repository
+        let url = pkgid_url(source);
+        let repository = url.trim_end_matches('/').trim_end_matches(".git");
+        let repository = repository.rsplit('/').next()?;
+        let commit = source.rsplit('#').next()?.get(..7)?;
+        let pattern = cargo_home
+            .join("git")
+            .join("checkouts")
+            .join(format!("{}-*", glob::Pattern::escape(repository)))
+            .join(commit)
+            .join("**")
+            .join("Cargo.toml");
+        return glob::glob(&pattern.to_string_lossy())
+            .ok()?
+            .flatten()
+            .find(|path| package_name(path).as_deref() == Some(name))
+            .and_then(|path| Some(path.parent()?.to_path_buf()));
+    }
+
+    //Registries are unpacked in `<host>-<hash>/<name>-<version>`. Crates.io has moved hosts.
+    let hosts = if CRATES_IO_SOURCES.contains(&source) {
+        vec!["index.crates.io".to_string(), "github.com".to_string()]
+    } else {
+        let url = pkgid_url(source.trim_start_matches("sparse+"));
+        vec![url.split("://").nth(1)?.split('/').next()?.to_string()]
+    };
+    let registries = fs::read_dir(cargo_home.join("registry").join("src")).ok()?;
+    registries
+        .flatten()
+        .filter(|r| {
+            let name = r.file_name();
+            let name = name.to_string_lossy();
+            hosts.iter().any(|h| name.starts_with(&format!("{}-", h)))
+        })
+        .map(|r| r.path().join(format!("{}-{}", name, version)))
+        .find(|dir| dir.join("Cargo.toml").is_file())
+}
+
+fn package_name(manifest_path: &Path) -> Option<String> {
+    let manifest: Value = toml::from_str(&fs::read_to_string(manifest_path).ok()?).ok()?;
+    Some(manifest.get("package")?.get("name")?.as_str()?.to_string())
+}
+
+//The manifest of a crate from Cargo.lock, if cargo has it in its caches
+fn cached_manifest(c: &Crate) -> Option<CargoToml> {
+    let dir = cached_manifest_dir(c.name, &c.version, c.lock_source?)?;
+    read_manifest(&dir).ok()
+}
+
+//`build.target-dir` of a cargo config file
+fn configured_target_dir(contents: &str) -> Result<Option<String>, String> {
+    let config: Value = toml::from_str(contents).map_err(|e| e.to_string())?;
+    match config.get("build").and_then(|b| b.get("target-dir")) {
+        Some(Value::String(target_dir)) => Ok(Some(target_dir.clone())),
+        Some(_) => Err("build.target-dir must be a string".to_string()),
+        None => Ok(None),
+    }
+}
+
+//Where cargo puts the docs in target_dir, which has a directory per target when one is requested
+fn doc_dir(target_dir: &Path, target: Option<&str>) -> PathBuf {
+    match target {
+        Some(target) => target_dir.join(target).join("doc"),
+        None => target_dir.join("doc"),
+    }
+}
+
+//Looks for the manifest of the workspace dir is a member of
+fn find_workspace_manifest(dir: &Path) -> Result<Option<(PathBuf, CargoToml)>, Error> {
+    for ancestor in dir.ancestors().skip(1) {
+        if ancestor.join("Cargo.toml").is_file() {
+            let manifest = read_manifest(ancestor)?;
+            if manifest.workspace.is_some() {
+                return Ok(Some((ancestor.to_path_buf(), manifest)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+//Loads the Cargo.lock in dir, the root of the workspace
+fn read_cargo_lock(dir: &Path) -> Result<CargoLock, Error> {
+    let lock_file = fs::read_to_string(dir.join("Cargo.lock")).map_err(|e| {
+        Error::Lock(format!(
+            "Expected Cargo.lock in workspace dir {} but couldn't open it: {}",
+            dir.to_string_lossy(),
+            e
+        ))
+    })?;
+
+    toml::from_str(&lock_file).map_err(|e| Error::Lock(format!("Lock file is invalid: {}", e)))
+}
+
+//A manifest of a crate to document, with the dependencies and overrides it inherits from its
+//workspace applied
+struct LoadedManifest {
+    dir: PathBuf,
+    manifest: CargoToml,
+    //Whether the member is selected for documentation
+    selected: bool,
+}
+
+//Reads Cargo.lock and the manifests of the crates to document in dir. With all, the workspace
+//members that aren't selected are read as well.
+fn load_manifests(
+    dir: PathBuf,
+    options: &Options,
+    all: bool,
+) -> Result<(CargoLock, Vec<LoadedManifest>), Error> {
+    let mut manifest = read_manifest(&dir)?;
+
+    //When not at the root of a workspace, the dependencies might be inherited from a workspace
+    //further up, and only its [patch] and [replace] sections apply. Cargo.lock is at its root.
+    let (workspace_dependencies, manifest_lock) = match manifest.workspace {
+        Some(ref workspace) => (workspace.dependencies.clone(), read_cargo_lock(&dir)?),
+        None => match find_workspace_manifest(&dir)? {
+            Some((root_dir, mut root)) => {
+                root.make_override_paths_absolute(&root_dir);
+                manifest.inherit_overrides(&root);
+                let manifest_lock = read_cargo_lock(&root_dir)?;
+                (root.workspace.and_then(|w| w.dependencies), manifest_lock)
+            }
+            None => (None, read_cargo_lock(&dir)?),
+        },
+    };
+    if manifest.workspace.is_some() {
+        manifest.make_override_paths_absolute(&dir);
+    }
+    manifest.inherit_workspace_dependencies(workspace_dependencies.as_ref())?;
+
+    let mut manifests = Vec::new(); //`cargo doc`'s -p argument doesn't work when used at the root
+                                    //of a workspace, so queue up operations
+    if let Some(workspace) = manifest.workspace.take() {
+        let mut all_members = Vec::new();
+        for member_dir in member_dirs(&dir, &workspace.members, &workspace.exclude)? {
+            let name = read_manifest(&member_dir)?.package.map(|p| p.name);
+            all_members.push(Member {
+                dir: member_dir,
+                name,
+            });
+        }
+        //A root package is a member too
+        if let Some(ref package) = manifest.package {
+            if !all_members.iter().any(|m| m.dir == dir) {
+                all_members.push(Member {
+                    dir: dir.clone(),
+                    name: Some(package.name.clone()),
+                });
+            }
+        }
+        let default_members = match workspace.default_members {
+            Some(ref default_members) => {
+                Some(member_dirs(&dir, default_members, &workspace.exclude)?)
+            }
+            None => None,
+        };
+
+        let members = select_members(&dir, &all_members, default_members, &options.members)?;
+        for member in &all_members {
+            let selected = members.contains(&member.dir);
+            //The root crate is handled below, its manifest is already loaded
+            if member.dir == dir || !(selected || all) {
+                continue;
+            }
+            let mut local_manifest = read_manifest(&member.dir)?;
+            local_manifest.inherit_workspace_dependencies(workspace_dependencies.as_ref())?;
+            local_manifest.inherit_overrides(&manifest);
+            manifests.push(LoadedManifest {
+                dir: member.dir.clone(),
+                manifest: local_manifest,
+                selected,
+            });
+        }
+        //Keep the order the members were selected in
+        manifests.sort_by_key(|m| members.iter().position(|d| *d == m.dir));
+
+        //Just because this Cargo.toml designates a workspace, does not mean it does not describe a crate,
+        //so document the root crate too if it is selected.
+        let selected = members.contains(&dir);
+        if selected || (all && manifest.package.is_some()) {
+            manifests.push(LoadedManifest {
+                dir,
+                manifest,
+                selected,
+            });
+        }
+    } else {
+        //Sigular crate, only one operation
+        manifest.make_override_paths_absolute(&dir);
+        manifests.push(LoadedManifest {
+            dir,
+            manifest,
+            selected: true,
+        });
+    }
+    Ok((manifest_lock, manifests))
+}
+
+//Finds the crates to document for dir and the selected workspace members by reading Cargo.toml
+//and Cargo.lock.
+fn manifest_crate_operations(dir: PathBuf, options: &Options) -> Result<Resolution, Error> {
+    let (manifest_lock, manifests) = load_manifests(dir, options, false)?;
+    let mut known_crates = Vec::new();
+    for p in &manifest_lock.package {
+        known_crates.push((p.name.clone(), Some(lock_version(p)?)));
+    }
+
+    let mut crate_operations = Vec::new();
+    for LoadedManifest { dir, manifest, .. } in manifests {
+        known_crates.extend(manifest.all_dependencies().map(|(k, _)| (k.clone(), None)));
+        let mut selection = get_crates(manifest, &manifest_lock, options)?;
+        for c in &mut selection.crates {
+            c.manifest_dir = c.manifest_dir.as_ref().map(|path| dir.join(path));
+        }
+        crate_operations.push((dir, selection));
+    }
+    Ok(Resolution {
+        operations: crate_operations,
+        known_crates,
+    })
+}
+
+//The settings for the manifest at manifest_path: the user's config file, then
+//`[workspace.metadata.makedocs]` of its workspace, its `[package.metadata.makedocs]` and finally the
+//`CARGO_MAKEDOCS_*` environment variables. The command line goes on top.
+pub fn load_config(manifest_path: &Path) -> Result<Config, Error> {
+    let dir = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+    let manifest = read_manifest(dir)?;
+    let mut config = Config::default();
+    config.layers.extend(config::user_config()?);
+    match manifest.workspace {
+        Some(_) => config.layers.push((
+            Source::Workspace(manifest_path.to_path_buf()),
+            manifest.workspace_config(),
+        )),
+        None => {
+            if let Some((path, root)) = find_workspace_manifest(dir)? {
+                config.layers.push((
+                    Source::Workspace(path.join("Cargo.toml")),
+                    root.workspace_config(),
+                ));
+            }
+        }
+    }
+    config.layers.push((
+        Source::Package(manifest_path.to_path_buf()),
+        manifest.package_config(),
+    ));
+    config.layers.push((
+        Source::Environment,
+        ProjectConfig::from_env(|name| env::var(name).ok())?,
+    ));
+    Ok(config)
+}
+
+//The messages for the checked patterns that match none of the known crates
+fn unmatched_patterns(
+    options: &Options,
+    known: &[(String, Option<Version>)],
+) -> Result<Vec<String>, Error> {
+    let patterns = [
+        ("exclude", &options.excluded_crates),
+        ("include", &options.extra_crates),
+    ];
+    let mut messages = Vec::new();
+    for (kind, patterns) in patterns.iter() {
+        for pattern in CratePattern::parse_all(*patterns)? {
+            if !options.checked_patterns.contains(&pattern.text) {
+                continue;
+            }
+            messages.extend(pattern.unmatched(kind, known));
+        }
+    }
+    Ok(messages)
+}
+
+//Finds the crates to document for the manifest at manifest_path and how to run `cargo doc` for them
+pub fn plan(manifest_path: &Path, options: &Options) -> Result<DocPlan, Error> {
+    let manifest_dir = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+    let dir = manifest_dir.canonicalize().map_err(|e| {
+        Error::Io(
+            format!(
+                "Couldn't resolve the path {}",
+                manifest_dir.to_string_lossy()
+            ),
+            e,
+        )
+    })?;
+
+    let mut warnings = Vec::new();
+    let resolution = match options.resolver {
+        Resolver::Metadata => match metadata::crate_operations(&dir, options) {
+            Ok(resolution) => resolution,
+            Err(e) => {
+                warnings.push(format!(
+                    "{}, falling back to reading Cargo.toml and Cargo.lock",
+                    e
+                ));
+                manifest_crate_operations(dir, options)?
+            }
+        },
+        Resolver::Manifest => manifest_crate_operations(dir, options)?,
+    };
+
+    for message in unmatched_patterns(options, &resolution.known_crates)? {
+        if options.strict {
+            return Err(Error::Config(message));
+        }
+        warnings.push(message);
+    }
+
+    //Cargo resolves --target-dir against the directory it is started in
+    let explicit_target_dir = match options.target_dir {
+        Some(target_dir) => Some(
+            env::current_dir()
+                .map_err(|e| Error::Io("Couldn't resolve --target-dir".to_string(), e))?
+                .join(target_dir),
+        ),
+        None => None,
+    };
+    let docs_rs = options.docs_rs && docsrs::is_nightly(options.toolchain)?;
+    if options.docs_rs && !docs_rs {
+        warnings.push("the docs.rs settings need a nightly toolchain, documenting without them. Pass --toolchain nightly to apply them.".to_string());
+    }
+    let mut operations = Vec::new();
+    let mut undeclared = options.features.features.clone();
+    let mut separate_dirs = Vec::new();
+    for (dir, mut selection) in resolution.operations {
+        warnings.append(&mut selection.warnings);
+        if selection.crates.is_empty() {
+            warnings.push(format!(
+                "no crates to document for dir {}",
+                dir.to_string_lossy()
+            ));
+        }
+        let target_dir = match explicit_target_dir {
+            Some(ref target_dir) => target_dir.clone(),
+            None => target_dir(&dir)?,
+        };
+        let features = if options.features.cargo_args().is_empty() {
+            Vec::new()
+        } else {
+            let manifest = read_manifest(&dir)?;
+            undeclared.retain(|f| !manifest.declares_feature(f));
+            options.features.member_cargo_args(&manifest)
+        };
+        let mut split = split_duplicate_crates(
+            dir,
+            selection.crates,
+            &target_dir,
+            options.target,
+            &mut separate_dirs,
+        );
+        split[0].excluded = selection.excluded;
+        for operation in &mut split {
+            operation.features = features.clone();
+        }
+        if docs_rs {
+            split = docsrs::split_docs_rs_crates(
+                split,
+                &target_dir,
+                options.target,
+                &mut separate_dirs,
+                &mut warnings,
+            )?;
+        }
+        operations.extend(split);
+    }
+    if !undeclared.is_empty() && !operations.is_empty() {
+        warnings.push(format!(
+            "no package declares the features {}",
+            undeclared.join(", ")
+        ));
+    }
+
+    Ok(DocPlan {
+        operations,
+        root: options.root,
+        document_private_items: options.document_private_items,
+        open: options.open,
+        features: options.features.cargo_args(),
+        cargo_args: options.cargo_args.iter().map(|a| a.to_string()).collect(),
+        target: options.target.map(str::to_string),
+        target_dir: explicit_target_dir,
+        profile: options.profile.map(str::to_string),
+        toolchain: options.toolchain.map(str::to_string),
+        fail_fast: options.fail_fast,
+        warnings,
+    })
+}
+
+impl DocPlan {
+    //The arguments choosing where and how cargo builds: the target dir, target and profile
+    fn build_arguments(&self, target_dir: Option<&PathBuf>) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(target_dir) = target_dir {
+            args.push("--target-dir".to_string());
+            args.push(target_dir.to_string_lossy().into_owned());
+        }
+        if let Some(ref target) = self.target {
+            args.push("--target".to_string());
+            args.push(target.clone());
+        }
+        if let Some(ref profile) = self.profile {
+            args.push("--profile".to_string());
+            args.push(profile.clone());
+        }
+        args
+    }
+}
+
+//A `cargo` invocation of a plan
+#[derive(Debug)]
+pub struct DocCommand<'a> {
+    pub dir: &'a Path,
+    pub args: Vec<String>,
+    //The operation documented by this command, or None for the run opening the docs
+    pub operation: Option<&'a Operation>,
+    pub toolchain: Option<&'a str>,
+    //Environment variables set for the command
+    pub env: Vec<(&'static str, String)>,
+}
+
+//Quotes arg for a POSIX shell if needed
+fn shell_quote(arg: &str) -> Cow<'_, str> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_.,:/@#+=%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', "'\\''")))
+    }
+}
+
+//The command as it would be typed into a shell
+impl<'a> fmt::Display for DocCommand<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cd {} && ", shell_quote(&self.dir.to_string_lossy()))?;
+        for (var, value) in &self.env {
+            write!(f, "{}={} ", var, shell_quote(value))?;
+        }
+        write!(f, "cargo")?;
+        if let Some(toolchain) = self.toolchain {
+            write!(f, " +{}", shell_quote(toolchain))?;
+        }
+        for arg in &self.args {
+            write!(f, " {}", shell_quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+//The `cargo doc` runs for every operation of the plan, followed by the one opening the docs if
+//requested. The root crate's package ID is looked up with `cargo pkgid`.
+pub fn commands(plan: &DocPlan) -> Result<Vec<DocCommand<'_>>, Error> {
+    let mut commands = Vec::new();
+    for operation in &plan.operations {
+        let dir = &operation.dir;
+        //Build command
+        //The plan warns about these
+        if operation.crates.is_empty() {
+            continue;
+        }
+
+        let mut args = vec!["doc".to_string(), "--no-deps".to_string()];
+        args.extend(create_arguments(&operation.crates).map(str::to_string));
+        let target_dir = operation.target_dir.as_ref().or(plan.target_dir.as_ref());
+        args.extend(plan.build_arguments(target_dir));
+
+        if plan.document_private_items {
+            args.push("--document-private-items".to_string());
+        }
+        args.extend(operation.features.iter().cloned());
+        let docs_rs = operation.docs_rs.as_ref();
+        let docs_rs_features = docs_rs.is_some_and(|d| !d.features.is_empty());
+        if let (Some(docs_rs), true) = (docs_rs, docs_rs_features) {
+            args.push("--features".to_string());
+            args.push(docs_rs.features.join(","));
+        }
+
+        //Cargo refuses feature flags unless a workspace member is selected, so the root crate
+        //gets documented as well in that case.
+        let root = plan.root && operation.target_dir.is_none();
+        if root || !operation.features.is_empty() || docs_rs_features {
+            args.push("-p".to_string());
+            args.push(root_package_id(dir, plan.toolchain.as_deref())?);
+        }
+        args.extend(plan.cargo_args.iter().cloned());
+
+        commands.push(DocCommand {
+            //`cargo doc` does not support the `-p` argument when at the root of a workspace.
+            dir,
+            args,
+            operation: Some(operation),
+            toolchain: plan.toolchain.as_deref(),
+            env: docs_rs.map(DocsRs::env).unwrap_or_default(),
+        });
+    }
+
+    //Open docs if requested. `cargo doc` doesn't allow --open with more than one -p argument, so
+    //it has to be run a second time for this.
+    let first = plan.operations.first().filter(|o| !o.crates.is_empty());
+    if let (true, Some(operation)) = (plan.open, first) {
+        let mut args = vec![
+            "doc".to_string(),
+            "--no-deps".to_string(),
+            "--open".to_string(),
+        ];
+
+        if !plan.root {
+            args.push("-p".to_string());
+            args.push(operation.crates[0].spec.clone());
+        }
+        //The same target dir and settings as the run that built the docs, or cargo rebuilds them
+        let target_dir = operation.target_dir.as_ref().or(plan.target_dir.as_ref());
+        args.extend(plan.build_arguments(target_dir));
+        let docs_rs = operation.docs_rs.as_ref();
+        let docs_rs_features = docs_rs.is_some_and(|d| !d.features.is_empty());
+
+        //The first -p argument decides what gets opened, so the root crate has to come after it
+        if (!operation.features.is_empty() || docs_rs_features) && !plan.root {
+            args.push("-p".to_string());
+            args.push(root_package_id(&operation.dir, plan.toolchain.as_deref())?);
+        }
+        args.extend(operation.features.iter().cloned());
+        if let (Some(docs_rs), true) = (docs_rs, docs_rs_features) {
+            args.push("--features".to_string());
+            args.push(docs_rs.features.join(","));
+        }
+        args.extend(plan.cargo_args.iter().cloned());
+
+        commands.push(DocCommand {
+            dir: &operation.dir,
+            args,
+            operation: None,
+            toolchain: plan.toolchain.as_deref(),
+            env: docs_rs.map(DocsRs::env).unwrap_or_default(),
+        });
+    }
+    Ok(commands)
+}
+
+//Runs `cargo doc` for every operation of the plan, and opens the docs if requested. Failed runs
+//are reported together at the end, unless the plan stops at the first one.
+pub fn execute(plan: &DocPlan) -> Result<(), Error> {
+    let commands = commands(plan)?;
+    let mut failed = Vec::new();
+    for (i, command) in commands.iter().enumerate() {
+        //There might be nothing to open when documenting failed
+        if command.operation.is_none() && !failed.is_empty() {
+            continue;
+        }
+        let dir = command.dir;
+
+        //Build documentation
+        let status = cargo_command(command.toolchain)
+            .current_dir(dir)
+            .args(&command.args)
+            .envs(command.env.iter().map(|(var, value)| (var, value)))
+            .status()
+            .map_err(|e| {
+                Error::Cargo(format!(
+                    "Couldn't run cargo doc in {}: {}",
+                    dir.to_string_lossy(),
+                    e
+                ))
+            })?;
+        if !status.success() {
+            failed.push(FailedRun {
+                dir: dir.to_path_buf(),
+                crates: command
+                    .operation
+                    .map(|o| o.crates.iter().map(|c| c.spec.clone()).collect())
+                    .unwrap_or_default(),
+                code: status.code(),
+            });
+            if plan.fail_fast {
+                //The run opening the docs is left out after a failure anyway
+                let skipped = commands[i + 1..]
+                    .iter()
+                    .filter(|c| c.operation.is_some())
+                    .count();
+                return Err(Error::DocFailed(failed, skipped));
+            }
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::DocFailed(failed, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cfg::{Cfg, Platform};
+    use super::{DependencyKinds, DocCrate, FeatureSelection, MemberSelection, Options, Resolver};
+
+    pub fn specs(crates: &[DocCrate]) -> Vec<&str> {
+        crates.iter().map(|c| c.spec.as_str()).collect()
+    }
+
+    //The options `cargo makedocs` runs with by default, on linux
+    pub fn options() -> Options<'static> {
+        Options {
+            excluded_crates: vec![],
+            extra_crates: vec![],
+            kinds: DependencyKinds {
+                normal: true,
+                build: true,
+                dev: false,
+            },
+            platform: Platform {
+                triple: "x86_64-unknown-linux-gnu".to_string(),
+                cfgs: vec![
+                    Cfg::Name("unix".to_string()),
+                    Cfg::KeyPair("target_os".to_string(), "linux".to_string()),
+                ],
+            },
+            features: FeatureSelection {
+                features: vec![],
+                all_features: false,
+                no_default_features: false,
+            },
+            depth: 0,
+            members: MemberSelection {
+                workspace: false,
+                packages: vec![],
+                excluded: vec![],
+            },
+            resolver: Resolver::Manifest,
+            root: false,
+            document_private_items: false,
+            open: false,
+            checked_patterns: vec![],
+            strict: false,
+            cargo_args: vec![],
+            target: None,
+            target_dir: None,
+            profile: None,
+            toolchain: None,
+            docs_rs: false,
+            fail_fast: false,
+        }
+    }
+
+    #[test]
+    fn get_crates_buildtime_deps() {
+        use super::get_crates;
+        let cargo_toml = r#"dependencies = {renamed = {package = "foo", version = "1.3"}}"#;
+        let cargo_lock = r#"[[package]]
+name="foo"
+version="1.3.5""#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let crates = get_crates(manifest, &manifest_lock, &options())
+            .unwrap()
+            .crates;
+        assert_eq!(specs(&crates), ["foo:1.3.5"]);
+    }
+    #[test]
+    fn get_crates_include_exclude_crate() {
+        use super::get_crates;
+        let cargo_toml = r#"dependencies = {some-crate = "1.0.0", foo = "1.2.0"}"#;
+        let cargo_lock = r#"[[package]]
+name = "some-crate"
+version="1.3.2"
+[[package]]
+name="foo"
+version="1.3.5"
+[[package]]
+name = "include-me"
+version="1.2.3""#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let options = Options {
+            excluded_crates: vec!["some-crate"],
+            extra_crates: vec!["include-me"],
+            ..options()
+        };
+        let crates = get_crates(manifest, &manifest_lock, &options)
+            .unwrap()
+            .crates;
+        assert_eq!(specs(&crates), ["foo:1.3.5", "include-me"]);
+    }
+
+    #[test]
+    fn get_crates_patterns() {
+        use super::get_crates;
+        let cargo_toml = r#"
+[dependencies]
+openssl-sys = "0.9"
+libz-sys = "1.0"
+tokio = "1.0"
+futures01 = { package = "futures", version = "0.1" }
+rand = "0.8"
+"#;
+        let cargo_lock = r#"[[package]]
+name = "futures"
+version = "0.1.29"
+[[package]]
+name = "libz-sys"
+version = "1.1.8"
+[[package]]
+name = "openssl-sys"
+version = "0.9.80"
+[[package]]
+name = "rand"
+version = "0.7.3"
+[[package]]
+name = "rand"
+version = "0.8.5"
+[[package]]
+name = "tokio"
+version = "1.28.0"
+[[package]]
+name = "tokio-macros"
+version = "2.1.0"
+[[package]]
+name = "tokio-util"
+version = "0.7.0"
+[[package]]
+name = "tokio-util"
+version = "0.7.8""#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let options = Options {
+            //futures01 is excluded by its package name
+            excluded_crates: vec!["*-sys", "futures", "rand@0.8"],
+            extra_crates: vec!["tokio-*", "rand@0.7"],
+            ..options()
+        };
+        let selection = get_crates(manifest, &manifest_lock, &options).unwrap();
+        let excluded: Vec<(&str, &str)> = selection
+            .excluded
+            .iter()
+            .map(|c| (c.key.as_str(), c.pattern.as_str()))
+            .collect();
+        assert_eq!(
+            excluded,
+            [
+                ("futures01", "futures"),
+                ("libz-sys", "*-sys"),
+                ("openssl-sys", "*-sys"),
+                ("rand", "rand@0.8")
+            ]
+        );
+        let crates = selection.crates;
+        assert_eq!(crates[0].requirement.as_deref(), Some("1.0"));
+        assert_eq!(crates[3].requirement.as_deref(), Some("=0.7"));
+        assert_eq!(
+            specs(&crates),
+            [
+                "tokio:1.28.0",
+                "tokio-macros:2.1.0",
+                "tokio-util:0.7.8",
+                "rand:0.7.3"
+            ]
+        );
+        assert!(crates[1..]
+            .iter()
+            .all(|c| c.reason == super::Reason::Included));
+    }
+
+    #[test]
+    fn commands_for_plan() {
+        use super::{commands, DocPlan, DocsRs, Operation};
+        use std::path::PathBuf;
+        let crates = |specs: &[&str]| specs.iter().map(|s| DocCrate::included(s, None)).collect();
+        let plan = DocPlan {
+            operations: vec![
+                Operation {
+                    dir: PathBuf::from("/ws/app"),
+                    crates: crates(&["futures:0.3.28", "serde"]),
+                    target_dir: None,
+                    doc_dir: PathBuf::from("/ws/target/wasm32-unknown-unknown/doc"),
+                    excluded: vec![],
+                    docs_rs: None,
+                    features: vec![],
+                },
+                Operation {
+                    dir: PathBuf::from("/ws/app"),
+                    crates: crates(&["futures:0.1.29"]),
+                    target_dir: Some(PathBuf::from("/ws/target/makedocs/futures01")),
+                    doc_dir: PathBuf::from(
+                        "/ws/target/makedocs/futures01/wasm32-unknown-unknown/doc",
+                    ),
+                    excluded: vec![],
+                    docs_rs: None,
+                    features: vec![],
+                },
+                Operation {
+                    dir: PathBuf::from("/ws/app"),
+                    crates: crates(&["memchr:2.7.1"]),
+                    target_dir: Some(PathBuf::from("/ws/target/makedocs/docs.rs/memchr")),
+                    doc_dir: PathBuf::from(
+                        "/ws/target/makedocs/docs.rs/memchr/wasm32-unknown-unknown/doc",
+                    ),
+                    excluded: vec![],
+                    docs_rs: Some(DocsRs {
+                        features: vec![],
+                        rustdoc_args: vec!["--cfg".to_string(), "docsrs".to_string()],
+                        rustc_args: vec![],
+                    }),
+                    features: vec![],
+                },
+                Operation {
+                    dir: PathBuf::from("/ws/my tool"),
+                    crates: vec![],
+                    target_dir: None,
+                    doc_dir: PathBuf::from("/ws/target/doc"),
+                    excluded: vec![],
+                    docs_rs: None,
+                    features: vec![],
+                },
+            ],
+            root: false,
+            document_private_items: true,
+            open: true,
+            features: vec![],
+            cargo_args: vec!["--offline".to_string()],
+            target: Some("wasm32-unknown-unknown".to_string()),
+            target_dir: None,
+            profile: Some("docs".to_string()),
+            toolchain: Some("nightly".to_string()),
+            fail_fast: false,
+            warnings: vec![],
+        };
+        let commands: Vec<String> = commands(&plan)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                "cd /ws/app && cargo +nightly doc --no-deps -p futures:0.3.28 -p serde --target wasm32-unknown-unknown --profile docs --document-private-items --offline",
+                "cd /ws/app && cargo +nightly doc --no-deps -p futures:0.1.29 --target-dir /ws/target/makedocs/futures01 --target wasm32-unknown-unknown --profile docs --document-private-items --offline",
+                "cd /ws/app && RUSTDOCFLAGS='--cfg docsrs' cargo +nightly doc --no-deps -p memchr:2.7.1 --target-dir /ws/target/makedocs/docs.rs/memchr --target wasm32-unknown-unknown --profile docs --document-private-items --offline",
+                "cd /ws/app && cargo +nightly doc --no-deps --open -p futures:0.3.28 --target wasm32-unknown-unknown --profile docs --offline",
+            ]
+        );
+        assert_eq!(super::shell_quote("/ws/my tool"), "'/ws/my tool'");
+    }
+
+    #[test]
+    fn doc_failure_summary() {
+        use super::{Error, FailedRun};
+        use std::path::PathBuf;
+        let error = Error::DocFailed(
+            vec![
+                FailedRun {
+                    dir: PathBuf::from("/ws/app"),
+                    crates: vec!["serde:1.0.190".to_string(), "rand:0.7.3".to_string()],
+                    code: Some(101),
+                },
+                FailedRun {
+                    dir: PathBuf::from("/ws/tool"),
+                    crates: vec![],
+                    code: None,
+                },
+            ],
+            2,
+        );
+        assert_eq!(error.exit_code(), 8);
+        assert_eq!(
+            error.to_string(),
+            "cargo doc failed in 2 run(s):
+  in /ws/app for serde:1.0.190, rand:0.7.3 (exit code 101)
+  in /ws/tool opening the docs (killed by a signal)
+2 run(s) skipped because of --fail-fast"
+        );
+    }
+
+    #[test]
+    fn get_crates_dev_deps() {
+        use super::get_crates;
+        let cargo_toml = r#"
+dependencies = {foo = "1.2.0"}
+dev-dependencies = {proptest = "0.9", excluded = "1.0"}"#;
+        let cargo_lock = r#"[[package]]
+name="foo"
+version="1.3.5"
+[[package]]
+name="proptest"
+version="0.9.4"
+[[package]]
+name="excluded"
+version="1.0.0""#;
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let options = Options {
+            excluded_crates: vec!["excluded"],
+            ..options()
+        };
+        let crates = get_crates(
+            toml::from_str(cargo_toml).unwrap(),
+            &manifest_lock,
+            &options,
+        )
+        .unwrap()
+        .crates;
+        assert_eq!(specs(&crates), ["foo:1.3.5"]);
+
+        let options = Options {
+            kinds: DependencyKinds {
+                normal: false,
+                build: false,
+                dev: true,
+            },
+            ..options
+        };
+        let crates = get_crates(
+            toml::from_str(cargo_toml).unwrap(),
+            &manifest_lock,
+            &options,
+        )
+        .unwrap()
+        .crates;
+        assert_eq!(specs(&crates), ["proptest:0.9.4"]);
+    }
+
+    #[test]
+    fn get_crates_missing_from_lock() {
+        use super::get_crates;
+        let manifest = toml::from_str("dependencies = {foo = \"1.2.0\", bar = \"0.1\"}").unwrap();
+        let manifest_lock = toml::from_str("[[package]]\nname=\"foo\"\nversion=\"1.3.5\"").unwrap();
+        let selection = get_crates(manifest, &manifest_lock, &options()).unwrap();
+        assert_eq!(specs(&selection.crates), ["bar", "foo:1.3.5"]);
+        assert_eq!(selection.warnings.len(), 1);
+        assert!(selection.warnings[0].starts_with("Crate bar not found in Cargo.lock"));
+    }
+
+    #[test]
+    fn get_crates_from_path() {
+        use super::get_crates;
+        let cargo_toml = r#"dependencies = {some-crate = { path = "some-crate" }}"#;
+        let cargo_lock = r#"[[package]]
+name = "some-crate"
+version="1.3.2"
+[[package]]
+name = "some-crate"
+version = "1.3.6""#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let crates = get_crates(manifest, &manifest_lock, &options())
+            .unwrap()
+            .crates;
+        assert_eq!(specs(&crates), ["some-crate:1.3.6"]);
+    }
+
+    #[test]
+    fn get_version_from_git() {
+        use super::get_crates;
+        let cargo_toml = r#"dependencies = {libc = { git = "https://github.com/rust-lang/libc" }}"#;
+        let cargo_lock = r#"[[package]]
+name = "libc"
+version = "0.2.43"
+source = "git+https://github.com/rust-lang/libc#9c5e70ae306463a23ec02179ac2c9fe05c3fb44e"
+"#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let crates = get_crates(manifest, &manifest_lock, &options())
+            .unwrap()
+            .crates;
+        assert_eq!(specs(&crates), ["libc:0.2.43"]);
+    }
+
+    #[test]
+    fn get_crates_target_deps() {
+        use super::{get_crates, DependencyKind, Reason};
+        let cargo_toml = r#"
+[dependencies]
+libc = "0.2"
+[target.'cfg(unix)'.dependencies]
+libc = "0.2"
+nix = "0.15"
+[target.'cfg(windows)'.dependencies]
+winapi = "0.3"
+[target.x86_64-unknown-linux-gnu.build-dependencies]
+cc = "1.0"
+[target.x86_64-pc-windows-msvc.build-dependencies]
+winres = "0.1"
+"#;
+        let cargo_lock = r#"[[package]]
+name = "libc"
+version = "0.2.43"
+[[package]]
+name = "nix"
+version = "0.15.0"
+[[package]]
+name = "winapi"
+version = "0.3.8"
+[[package]]
+name = "cc"
+version = "1.0.50"
+[[package]]
+name = "winres"
+version = "0.1.11""#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let crates = get_crates(manifest, &manifest_lock, &options())
+            .unwrap()
+            .crates;
+        assert_eq!(specs(&crates), ["libc:0.2.43", "nix:0.15.0", "cc:1.0.50"]);
+        assert_eq!(
+            crates[2].reason,
+            Reason::Dependency {
+                kind: DependencyKind::Build,
+                target: Some("x86_64-unknown-linux-gnu".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn get_crates_optional_deps() {
+        use super::get_crates;
+        let cargo_toml = r#"
+[dependencies]
+serde = { version = "1.0", optional = true }
+serde_json = { version = "1.0", optional = true }
+rayon = { version = "1.3", optional = true }
+log = "0.4"
+[features]
+default = ["parallel"]
+parallel = ["rayon"]
+json = ["dep:serde_json", "serde?/std"]
+"#;
+        let cargo_lock = r#"[[package]]
+name = "serde"
+version = "1.0.104"
+[[package]]
+name = "serde_json"
+version = "1.0.48"
+[[package]]
+name = "rayon"
+version = "1.3.0"
+[[package]]
+name = "log"
+version = "0.4.8""#;
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let crates = get_crates(
+            toml::from_str(cargo_toml).unwrap(),
+            &manifest_lock,
+            &options(),
+        )
+        .unwrap()
+        .crates;
+        assert_eq!(specs(&crates), ["log:0.4.8", "rayon:1.3.0"]);
+
+        let options = Options {
+            features: FeatureSelection {
+                features: vec!["json"],
+                all_features: false,
+                no_default_features: true,
+            },
+            ..options()
+        };
+        let crates = get_crates(
+            toml::from_str(cargo_toml).unwrap(),
+            &manifest_lock,
+            &options,
+        )
+        .unwrap()
+        .crates;
+        assert_eq!(specs(&crates), ["log:0.4.8", "serde_json:1.0.48"]);
+
+        let options = Options {
+            features: FeatureSelection {
+                features: vec!["serde"],
+                all_features: false,
+                no_default_features: true,
+            },
+            ..options
+        };
+        let crates = get_crates(
+            toml::from_str(cargo_toml).unwrap(),
+            &manifest_lock,
+            &options,
+        )
+        .unwrap()
+        .crates;
+        assert_eq!(specs(&crates), ["log:0.4.8", "serde:1.0.104"]);
+    }
+
+    #[test]
+    fn get_crates_inherited_from_workspace() {
+        use super::{get_crates, CargoToml};
+        let workspace_toml = r#"
+[workspace]
+members = ["member"]
+[workspace.dependencies]
+serde = { version = "1.0", features = ["derive"] }
+rand = "0.7"
+futures01 = { package = "futures", version = "0.1" }
+local = { path = "local" }
+"#;
+        let cargo_toml = r#"
+[dependencies]
+serde = { workspace = true, features = ["rc"] }
+futures01 = { workspace = true }
+local.workspace = true
+[dev-dependencies]
+rand = { workspace = true }
+"#;
+        let cargo_lock = r#"[[package]]
+name = "serde"
+version = "1.0.104"
+[[package]]
+name = "rand"
+version = "0.7.3"
+[[package]]
+name = "futures"
+version = "0.1.29"
+[[package]]
+name = "futures"
+version = "0.3.4"
+[[package]]
+name = "local"
+version = "0.1.0""#;
+        let workspace: CargoToml = toml::from_str(workspace_toml).unwrap();
+        let workspace_dependencies = workspace.workspace.unwrap().dependencies;
+        let mut manifest: CargoToml = toml::from_str(cargo_toml).unwrap();
+        manifest
+            .inherit_workspace_dependencies(workspace_dependencies.as_ref())
+            .unwrap();
+
+        let serde = &manifest.dependencies.dependencies.as_ref().unwrap()["serde"];
+        assert_eq!(serde["version"].as_str(), Some("1.0"));
+        assert_eq!(
+            serde["features"].as_array().unwrap(),
+            &[toml::Value::from("derive"), toml::Value::from("rc")]
+        );
+
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let options = Options {
+            kinds: DependencyKinds {
+                normal: true,
+                build: true,
+                dev: true,
+            },
+            ..options()
+        };
+        let crates = get_crates(manifest, &manifest_lock, &options)
+            .unwrap()
+            .crates;
+        assert_eq!(
+            specs(&crates),
+            [
+                "futures:0.1.29",
+                "local:0.1.0",
+                "serde:1.0.104",
+                "rand:0.7.3"
+            ]
+        );
+
+        let mut manifest: CargoToml = toml::from_str(cargo_toml).unwrap();
+        assert!(manifest.inherit_workspace_dependencies(None).is_err());
+    }
+
+    #[test]
+    fn workspace_member_globs() {
+        use super::{member_dirs, Workspace};
+        use std::fs;
+
+        let root =
+            std::env::temp_dir().join(format!("cargo-makedocs-members-{}", std::process::id()));
+        for dir in &[
+            "crates/a",
+            "crates/b",
+            "crates/no-manifest",
+            "crates/excluded",
+            "tools/c",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            if !dir.ends_with("no-manifest") {
+                fs::write(root.join(dir).join("Cargo.toml"), "").unwrap();
+            }
+        }
+
+        let workspace: Workspace = toml::from_str(
+            r#"
+members = ["crates/*", "tools/c"]
+exclude = ["crates/excluded"]"#,
+        )
+        .unwrap();
+        let dirs = member_dirs(&root, &workspace.members, &workspace.exclude).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            dirs,
+            [
+                root.join("crates/a"),
+                root.join("crates/b"),
+                root.join("tools/c")
+            ]
+        );
+    }
+
+    #[test]
+    fn lock_of_nested_member() {
+        use super::manifest_crate_operations;
+        use std::fs;
+
+        let root =
+            std::env::temp_dir().join(format!("cargo-makedocs-nested-{}", std::process::id()));
+        let member = root.join("crates").join("b");
+        fs::create_dir_all(&member).unwrap();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]",
+        )
+        .unwrap();
+        fs::write(
+            root.join("Cargo.lock"),
+            "[[package]]\nname = \"b\"\nversion = \"0.1.0\"\n[[package]]\nname = \"log\"\nversion = \"0.4.8\"",
+        )
+        .unwrap();
+        fs::write(
+            member.join("Cargo.toml"),
+            "[package]\nname = \"b\"\nversion = \"0.1.0\"\n[dependencies]\nlog = \"0.4\"",
+        )
+        .unwrap();
+        let resolution = manifest_crate_operations(member.clone(), &options());
+        fs::remove_dir_all(&root).unwrap();
+        let operations = resolution.unwrap().operations;
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].0, member);
+        assert_eq!(specs(&operations[0].1.crates), ["log:0.4.8"]);
+    }
+
+    #[test]
+    fn select_workspace_members() {
+        use super::{select_members, Member, MemberSelection};
+        use std::path::Path;
+
+        let root = Path::new("/workspace");
+        let all: Vec<Member> = ["server", "client", "shared"]
+            .iter()
+            .map(|name| Member {
+                dir: root.join(name),
+                name: Some(format!("{}-crate", name)),
+            })
+            .collect();
+        let select = |workspace, packages, excluded| {
+            let selection = MemberSelection {
+                workspace,
+                packages,
+                excluded,
+            };
+            select_members(root, &all, Some(vec![root.join("server")]), &selection)
+        };
+
+        assert_eq!(
+            select(false, vec![], vec![]).unwrap(),
+            [root.join("server")]
+        );
+        assert_eq!(
+            select(true, vec![], vec!["shared-crate"]).unwrap(),
+            [root.join("server"), root.join("client")]
+        );
+        assert_eq!(
+            select(false, vec!["client-crate", "shared"], vec![]).unwrap(),
+            [root.join("client"), root.join("shared")]
+        );
+        assert!(select(false, vec!["nope"], vec![]).is_err());
+    }
+
+    #[test]
+    fn features_per_member() {
+        use super::{CargoToml, FeatureSelection};
+        let manifest: CargoToml = toml::from_str(
+            r#"
+[package]
+name = "a"
+[features]
+fast = []
+[dependencies]
+serde = { version = "1", optional = true }
+log = "0.4"
+"#,
+        )
+        .unwrap();
+        let selection = FeatureSelection {
+            features: vec![
+                "fast", "serde", "log", "log/std", "a/fast", "b/fast", "slow",
+            ],
+            all_features: false,
+            no_default_features: true,
+        };
+        assert_eq!(
+            selection.member_cargo_args(&manifest),
+            [
+                "--features",
+                "fast,serde,log/std,a/fast",
+                "--no-default-features"
+            ]
+        );
+        let none = FeatureSelection {
+            features: vec!["slow"],
+            all_features: false,
+            no_default_features: false,
+        };
+        assert!(none.member_cargo_args(&manifest).is_empty());
+    }
+
+    #[test]
+    fn transitive_deps_for_platform() {
+        use super::{CargoLock, CargoToml, Crate};
+        let cargo_lock = r#"[[package]]
+name = "atty"
+version = "0.2.14"
+dependencies = ["hermit-abi", "libc", "winapi"]
+[[package]]
+name = "hermit-abi"
+version = "0.1.19"
+[[package]]
+name = "libc"
+version = "0.2.150"
+[[package]]
+name = "winapi"
+version = "0.3.9""#;
+        let atty_toml = r#"
+[package]
+name = "atty"
+version = "0.2.14"
+[target.'cfg(target_os = "hermit")'.dependencies]
+hermit-abi = "0.1.6"
+[target.'cfg(unix)'.dependencies]
+libc = { version = "0.2", default-features = false }
+[target.'cfg(windows)'.dependencies]
+winapi = "0.3""#;
+        let lock: CargoLock = toml::from_str(cargo_lock).unwrap();
+        let atty = lock
+            .resolved(lock.find("atty", None, None).unwrap())
+            .unwrap();
+        let manifest_of = |c: &Crate| -> Option<CargoToml> {
+            Some(c.name)
+                .filter(|n| *n == "atty")
+                .map(|_| toml::from_str(atty_toml).unwrap())
+        };
+        let found = lock
+            .transitive_dependencies(&[atty], 1, &[], &options().platform, &manifest_of)
+            .unwrap();
+        let found: Vec<String> = found.iter().map(|(c, _)| c.to_string()).collect();
+        assert_eq!(found, ["libc:0.2.150"]);
+    }
+
+    #[test]
+    fn get_crates_transitive_deps() {
+        use super::{get_crates, Reason};
+        let cargo_toml = r#"dependencies = {axum = "0.2", log = "0.4"}"#;
+        let cargo_lock = r#"[[package]]
+name = "axum"
+version = "0.2.3"
+dependencies = ["http 0.2.1", "tower", "log"]
+[[package]]
+name = "tower"
+version = "0.4.8"
+dependencies = ["tower-layer", "http 0.2.1"]
+[[package]]
+name = "tower-layer"
+version = "0.3.1"
+[[package]]
+name = "http"
+version = "0.1.21"
+[[package]]
+name = "http"
+version = "0.2.1"
+dependencies = ["bytes"]
+[[package]]
+name = "bytes"
+version = "0.5.4"
+[[package]]
+name = "log"
+version = "0.4.8""#;
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let options = Options {
+            depth: 1,
+            ..options()
+        };
+        let crates = get_crates(
+            toml::from_str(cargo_toml).unwrap(),
+            &manifest_lock,
+            &options,
+        )
+        .unwrap()
+        .crates;
+        assert_eq!(
+            specs(&crates),
+            ["axum:0.2.3", "log:0.4.8", "http:0.2.1", "tower:0.4.8"]
+        );
+
+        let options = Options {
+            depth: 2,
+            excluded_crates: vec!["bytes"],
+            ..options
+        };
+        let crates = get_crates(
+            toml::from_str(cargo_toml).unwrap(),
+            &manifest_lock,
+            &options,
+        )
+        .unwrap()
+        .crates;
+        assert_eq!(
+            specs(&crates),
+            [
+                "axum:0.2.3",
+                "log:0.4.8",
+                "http:0.2.1",
+                "tower:0.4.8",
+                "tower-layer:0.3.1"
+            ]
+        );
+        assert_eq!(
+            crates[4].reason,
+            Reason::Transitive {
+                parent: "tower".to_string(),
+                depth: 2
+            }
+        );
+    }
+
+    #[test]
+    fn get_crates_same_name_different_sources() {
+        use super::get_crates;
+        let cargo_toml = r#"
+[dependencies]
+libc = "0.2"
+libc-fork = { package = "libc", git = "https://github.com/someone/libc", branch = "fix" }
+rand = { git = "https://github.com/rust-random/rand.git", tag = "0.7.3" }
+"#;
+        let cargo_lock = r#"[[package]]
+name = "libc"
+version = "0.2.43"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+[[package]]
+name = "libc"
+version = "0.2.43"
+source = "git+https://github.com/someone/libc?branch=fix#9c5e70ae306463a23ec02179ac2c9fe05c3fb44e"
+[[package]]
+name = "rand"
+version = "0.7.2"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "git+https://github.com/rust-random/rand?tag=0.7.3#bb2c5a3c1bb2bc4fba64b73c3a2bd0c3ed3b4aac""#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let crates = get_crates(manifest, &manifest_lock, &options())
+            .unwrap()
+            .crates;
+        assert_eq!(
+            specs(&crates),
+            [
+                "https://github.com/rust-lang/crates.io-index#libc@0.2.43",
+                "https://github.com/someone/libc#libc@0.2.43",
+                "rand:0.7.3"
+            ]
+        );
+    }
+
+    #[test]
+    fn split_renamed_duplicates() {
+        use super::{split_duplicate_crates, DependencyKind, DocCrate, Reason};
+        use std::path::Path;
+        let doc_crate = |key: &str, name: &str, spec: &str| DocCrate {
+            key: key.to_string(),
+            name: name.to_string(),
+            spec: spec.to_string(),
+            requirement: None,
+            version: None,
+            source: None,
+            manifest_dir: None,
+            reason: Reason::Dependency {
+                kind: DependencyKind::Normal,
+                target: None,
+            },
+        };
+        let crates = vec![
+            doc_crate("futures01", "futures", "futures:0.1.29"),
+            doc_crate("log", "log", "log:0.4.8"),
+            doc_crate("futures", "futures", "futures:0.3.4"),
+        ];
+        let operations = split_duplicate_crates(
+            Path::new("/app").to_path_buf(),
+            crates,
+            Path::new("/app/target"),
+            Some("thumbv7em-none-eabihf"),
+            &mut Vec::new(),
+        );
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].target_dir, None);
+        assert_eq!(specs(&operations[0].crates), ["log:0.4.8", "futures:0.3.4"]);
+        assert_eq!(
+            operations[1].target_dir.as_deref(),
+            Some(Path::new("/app/target/makedocs/futures01"))
+        );
+        assert_eq!(specs(&operations[1].crates), ["futures:0.1.29"]);
+        assert_eq!(
+            operations[0].doc_dir,
+            Path::new("/app/target/thumbv7em-none-eabihf/doc")
+        );
+        assert_eq!(
+            operations[1].doc_path(&operations[1].crates[0]),
+            Path::new(
+                "/app/target/makedocs/futures01/thumbv7em-none-eabihf/doc/futures/index.html"
+            )
+        );
+    }
+
+    #[test]
+    fn split_transitive_duplicates() {
+        use super::{split_duplicate_crates, DocCrate, Reason};
+        use semver::Version;
+        use std::path::{Path, PathBuf};
+        let transitive = |version: &str| DocCrate {
+            key: "bitflags".to_string(),
+            name: "bitflags".to_string(),
+            spec: format!("bitflags:{}", version),
+            requirement: None,
+            version: Some(Version::parse(version).unwrap()),
+            source: None,
+            manifest_dir: None,
+            reason: Reason::Transitive {
+                parent: "app".to_string(),
+                depth: 1,
+            },
+        };
+        let crates = vec![
+            transitive("0.9.1"),
+            transitive("1.3.2"),
+            transitive("2.4.0"),
+        ];
+        let mut used = Vec::new();
+        let operations = split_duplicate_crates(
+            Path::new("/app").to_path_buf(),
+            crates,
+            Path::new("/app/target"),
+            None,
+            &mut used,
+        );
+        let target_dirs: Vec<Option<PathBuf>> =
+            operations.iter().map(|o| o.target_dir.clone()).collect();
+        assert_eq!(
+            target_dirs,
+            [
+                None,
+                Some(PathBuf::from("/app/target/makedocs/bitflags")),
+                Some(PathBuf::from("/app/target/makedocs/bitflags-2.4.0")),
+            ]
+        );
+
+        //Another member's run with the same crate doesn't reuse the dirs either
+        let operations = split_duplicate_crates(
+            Path::new("/app/tool").to_path_buf(),
+            vec![transitive("0.9.1"), transitive("1.3.2")],
+            Path::new("/app/target"),
+            None,
+            &mut used,
+        );
+        assert_eq!(
+            operations[1].target_dir.as_deref(),
+            Some(Path::new("/app/target/makedocs/bitflags-1.3.2"))
+        );
+    }
+
+    #[test]
+    fn only_command_line_patterns_checked() {
+        use super::{unmatched_patterns, Options};
+        use semver::Version;
+        let known = vec![(
+            "serde".to_string(),
+            Some(Version::parse("1.0.190").unwrap()),
+        )];
+        //`*-sys` comes from the user's settings, `serd` from -e
+        let options = Options {
+            excluded_crates: vec!["*-sys", "serd"],
+            extra_crates: vec!["tokio"],
+            checked_patterns: vec!["serd"],
+            ..options()
+        };
+        let messages = unmatched_patterns(&options, &known).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("exclude pattern serd matches no dependency"));
+    }
+
+    #[test]
+    fn cargo_for_toolchain() {
+        use super::cargo_command;
+        use std::env;
+        use std::ffi::OsStr;
+        //Tests run under cargo, which sets $CARGO
+        let command = cargo_command(None);
+        assert_eq!(
+            command.get_program(),
+            env::var_os("CARGO").unwrap_or_else(|| "cargo".into())
+        );
+        assert_eq!(command.get_args().count(), 0);
+
+        let command = cargo_command(Some("nightly"));
+        assert_eq!(command.get_program(), "cargo");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [OsStr::new("+nightly")]
+        );
+    }
+
+    #[test]
+    fn cargo_config_target_dir() {
+        use super::configured_target_dir;
+        assert_eq!(
+            configured_target_dir("[build]\ntarget-dir = \"out\"\njobs = 4"),
+            Ok(Some("out".to_string()))
+        );
+        assert_eq!(configured_target_dir("[net]\noffline = true"), Ok(None));
+        assert!(configured_target_dir("[build]\ntarget-dir = 1").is_err());
+        assert!(configured_target_dir("[build").is_err());
+    }
+
+    #[test]
+    fn get_crates_patched_and_replaced() {
+        use super::get_crates;
+        let cargo_toml = r#"
+[dependencies]
+log = "0.4"
+libc = "0.2"
+rand = "0.7"
+
+[patch.crates-io]
+log = { git = "https://github.com/rust-lang/log", branch = "fix" }
+rand = { path = "/src/rand" }
+
+[replace]
+"libc:0.2.43" = { path = "/src/libc" }
+"#;
+        let cargo_lock = r#"[[package]]
+name = "log"
+version = "0.4.6"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+[[package]]
+name = "log"
+version = "0.4.8"
+source = "git+https://github.com/rust-lang/log?branch=fix#0c64e3c1ee63a2a0bc9a0cb4e6b2fd3a72bb2bde"
+[[package]]
+name = "libc"
+version = "0.2.43"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+replace = "libc 0.2.43"
+[[package]]
+name = "libc"
+version = "0.2.43"
+[[package]]
+name = "rand"
+version = "0.7.3""#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let crates = get_crates(manifest, &manifest_lock, &options())
+            .unwrap()
+            .crates;
+        assert_eq!(
+            specs(&crates),
+            [
+                "path+file:///src/libc#libc@0.2.43",
+                "log:0.4.8",
+                "rand:0.7.3"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_versions_are_errors() {
+        use super::error::Error;
+        use super::get_crates;
+        let cargo_lock = r#"[[package]]
+name = "foo"
+version = "1.3.5"
+source = "registry+https://github.com/rust-lang/crates.io-index""#;
+        let manifest = toml::from_str("[dependencies]\nfoo = \"1.0 || 2.0\"").unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        match get_crates(manifest, &manifest_lock, &options()) {
+            Err(e @ Error::VersionReq(_)) => assert_eq!(e.exit_code(), 4),
+            other => panic!("expected a version requirement error, got {:?}", other),
+        }
+
+        let manifest = toml::from_str("[dependencies]\nfoo = \"1\"").unwrap();
+        let manifest_lock = toml::from_str(&cargo_lock.replace("1.3.5", "one")).unwrap();
+        match get_crates(manifest, &manifest_lock, &options()) {
+            Err(e @ Error::Lock(_)) => assert_eq!(e.exit_code(), 3),
+            other => panic!("expected a lock error, got {:?}", other),
+        }
+
+        let manifest = toml::from_str("[dependencies]\nfoo = { features = [] }").unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        assert!(matches!(
+            get_crates(manifest, &manifest_lock, &options()),
+            Err(Error::Manifest(_))
+        ));
+    }
+
+    #[test]
+    fn get_crates_package_config() {
+        use super::get_crates;
+        let cargo_toml = r#"
+[package]
+name = "app"
+
+[package.metadata.makedocs]
+exclude = ["futures01"]
+include = ["tokio-util"]
+
+[package.metadata.docs.rs]
+all-features = true
+
+[dependencies]
+futures01 = { package = "futures", version = "0.1" }
+log = "0.4"
+"#;
+        let cargo_lock = r#"[[package]]
+name = "futures"
+version = "0.1.29"
+[[package]]
+name = "log"
+version = "0.4.8"
+[[package]]
+name = "tokio-util"
+version = "0.3.1""#;
+        let manifest = toml::from_str(cargo_toml).unwrap();
+        let manifest_lock = toml::from_str(cargo_lock).unwrap();
+        let crates = get_crates(manifest, &manifest_lock, &options())
+            .unwrap()
+            .crates;
+        assert_eq!(specs(&crates), ["log:0.4.8", "tokio-util"]);
+    }
+}
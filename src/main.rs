@@ -1,348 +1,211 @@
-use clap::{App, AppSettings, Arg, SubCommand};
-use semver::{Version, VersionReq};
-use serde_derive::Deserialize;
-use std::env;
-use std::fmt;
-use std::fs::File;
-use std::io::Read;
-use std::path::{Path, PathBuf};
-use std::process::{exit, Command};
-use toml::value::{self, Value};
-
-#[derive(Deserialize)]
-struct Workspace {
-    members: Vec<String>,
-}
-
-#[derive(Deserialize)]
-struct CargoToml {
-    dependencies: Option<value::Table>,
-    #[serde(rename = "build-dependencies")]
-    build_dependencies: Option<value::Table>,
-    workspace: Option<Workspace>,
-}
-
-#[derive(Deserialize)]
-struct CargoLock {
-    package: Vec<LockEntry>,
-}
-
-#[derive(Deserialize)]
-struct LockEntry {
-    name: String,
-    version: String,
+use cargo_makedocs::cfg::Platform;
+use cargo_makedocs::config::{ProjectConfig, Source};
+use cargo_makedocs::explain::explain;
+use cargo_makedocs::{
+    commands, execute, find_manifest, load_config, plan, DependencyKinds, Error, FeatureSelection,
+    MemberSelection, Options, Resolver,
+};
+use clap::{value_t, App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::path::Path;
+use std::process::exit;
+
+//The options of `cargo makedocs`. They can be given before and after `explain <crate>`, and clap
+//keeps each with the subcommand it was given to.
+struct Args<'a> {
+    levels: Vec<&'a ArgMatches<'a>>,
 }
 
-#[derive(Debug)]
-struct Crate<'a> {
-    pub name: &'a str,
-    pub version: Version,
-}
-
-impl<'a> fmt::Display for Crate<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}:{}", self.name, self.version)
+impl<'a> Args<'a> {
+    fn new(matches: &'a ArgMatches<'a>) -> Args<'a> {
+        let mut levels = vec![matches];
+        levels.extend(matches.subcommand_matches("explain"));
+        Args { levels }
     }
-}
 
-//Assumes the syntax of cargo.lock is correct
-fn correct_version<'a>(lock: &'a CargoLock, name: &str, version: &str) -> String {
-    let mut out = Vec::new();
-    let crate_version = VersionReq::parse(version).unwrap();
-    lock.package
-        .iter()
-        .filter(|x| x.name == name)
-        .for_each(|p| {
-            //Push the matching version numbers onto out
-            let lock_version = Version::parse(&p.version.as_str()).unwrap();
-            if crate_version.matches(&lock_version) {
-                out.push(Crate {
-                    name: &p.name,
-                    version: lock_version,
-                });
-            }
-        });
+    //The matches the option was given in, preferring the innermost one and explicit values over
+    //defaults
+    fn find(&self, name: &str) -> Option<&'a ArgMatches<'a>> {
+        let levels = || self.levels.iter().rev().copied();
+        levels()
+            .find(|m| m.occurrences_of(name) > 0)
+            .or_else(|| levels().find(|m| m.is_present(name)))
+    }
 
-    //Ensure we use the most up to date, compatible crate
-    out.sort_unstable_by(|x, y| y.version.cmp(&x.version));
-    out.dedup_by(|x, y| x.name == y.name);
+    fn is_present(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
 
-    //out can be zero-length if you run cargo-makedocs before cargo build.
-    //Pass just the crate name to get cargo to add it
-    if out.is_empty() {
-        eprintln!("cargo-makedocs: Crate {} not found in Cargo.lock, please run `cargo build`. `cargo doc` might fail or doc the wrong version.", name);
-        name.to_string()
-    } else {
-        format!("{}", out[0])
+    fn value_of(&self, name: &str) -> Option<&'a str> {
+        self.find(name)?.value_of(name)
     }
-    // debug_assert_eq!(out.len(), 1);
-}
 
-fn get_crates(
-    manifest: CargoToml,
-    manifest_lock: &CargoLock,
-    excluded_crates: &[&str],
-    extra_crates: &[&str],
-    buildtime: bool,
-) -> Result<Vec<String>, String> {
-    Ok(manifest
-        .dependencies
-        .iter()
-        .flatten()
-        .chain(
-            //Include or ignore buildtime dependencies
-            if buildtime {
-                manifest.build_dependencies
-            } else {
-                None
-            }
+    fn values_of(&self, name: &str) -> Vec<&'a str> {
+        self.levels
             .iter()
-            .flatten(),
-        )
-        .filter_map(|(k, v)| {
-            if !excluded_crates.contains(&k.as_str()) {
-                let mut changed_name = None;
-                //If multiple versions of a library is flying about we need to specify the correct version
-                let version = match v {
-                    //If the dependency is added as [dependencies.<crate>], this needs to be handled
-                    Value::Table(t) => {
-                        if let Some(name) = t.get("package") {
-                            //Package is renamed
-                            changed_name = Some(name.as_str().unwrap());
-                        }
-                        if let Some(v) = t.get("version") {
-                            v.as_str().unwrap()
-                        } else if t.get("path").is_some() || t.get("git").is_some() {
-                            "*" //Assume that the user is developing the dependency if using a path
-                                //and that if using git, wants the latest version available
-                        } else {
-                            eprintln!("cargo-makedocs: dependency {} is invalid", k);
-                            exit(1);
-                        }
-                    }
-                    Value::String(s) => s,
-                    _ => {
-                        eprintln!(
-                            "cargo-makedocs: couldn't parse Cargo.toml: invalid value in key {}",
-                            k
-                        );
-                        exit(1);
-                    }
-                };
-
-                //Get the compatible version from Cargo.lock to always build the correct version
-                Some(correct_version(
-                    &manifest_lock,
-                    changed_name.unwrap_or(k),
-                    &version,
-                ))
-            } else {
-                None
-            }
-        })
-        .chain(extra_crates.iter().map(std::string::ToString::to_string))
-        .collect())
-}
-
-fn create_arguments(input: &[String]) -> Vec<&str> {
-    input.iter().flat_map(|s| vec!["-p", s]).collect()
-}
-
-//Looks for Cargo.toml in every directory above the current directory.
-fn find_rootdir() -> Result<PathBuf, String> {
-    match env::current_dir() {
-        Ok(dir) => match dir
-            .ancestors()
-            .flat_map(|a| {
-                a.read_dir()
-                    .map(|f| f.map(|entry| entry.unwrap().path()))
-                    .unwrap()
-            })
-            .find(|buf| buf.file_name() == Some("Cargo.toml".as_ref()))
-        {
-            Some(mut s) => {
-                s.pop();
-                Ok(s)
-            }
-            None => Err("Cannot find Cargo.toml in any ancestor directory".to_string()),
-        },
-        Err(e) => Err(format!("Can't find Cargo.toml: {}", e)),
+            .flat_map(|m| m.values_of(name).into_iter().flatten())
+            .collect()
     }
-}
 
-//Attempts to find Cargo.lock in starting_dir and the parent dir and load it to a String.
-fn read_cargo_lock(starting_dir: &Path) -> Result<CargoLock, String> {
-    let mut lock_file = String::new();
-    match File::open(starting_dir.join("Cargo.lock")) {
-        Ok(mut f) => {
-            f.read_to_string(&mut lock_file).unwrap();
-        }
-        Err(e) => {
-            //Try the parent directory as if starting_dir is inside a workspace.
-            if let Some(parent) = starting_dir.parent() {
-                match File::open(parent.join("Cargo.lock")) {
-                    Ok(mut f) => {
-                        f.read_to_string(&mut lock_file).unwrap();
-                    }
-                    Err(e) => {
-                        return Err(format!(
-                            "Expected Cargo.lock in workspace dir {} but couldn't open it: {}",
-                            parent.to_string_lossy(),
-                            e
-                        ))
-                    }
-                }
-            } else {
-                return Err(format!("Couldn't open Cargo.lock: {}", e));
-            }
-        }
+    fn subcommand_matches(&self, name: &str) -> Option<&'a ArgMatches<'a>> {
+        self.levels[0].subcommand_matches(name)
     }
-
-    toml::from_str(&lock_file).map_err(|e| format!("Lock file is invalid: {}", e))
 }
 
-fn run(matches: &clap::ArgMatches) -> Result<(), String> {
-    let excluded_crates: Vec<&str> = match matches.values_of("exclude") {
-        Some(ex) => ex.collect(),
-        None => vec![],
+fn run(matches: &Args) -> Result<(), Error> {
+    let manifest_path = find_manifest()?;
+    //The user's and the project's settings are defaults, the flags override them
+    let mut layers = load_config(&manifest_path)?;
+    let values = |name| -> Vec<String> {
+        matches
+            .values_of(name)
+            .into_iter()
+            .map(str::to_string)
+            .collect()
     };
-
-    let extra_crates: Vec<&str> = match matches.values_of("include") {
-        Some(ex) => ex.collect(),
-        None => vec![],
+    //A flag and its negation, like --open and --no-open
+    let flag = |on, off| {
+        if matches.is_present(on) {
+            Some(true)
+        } else if matches.is_present(off) {
+            Some(false)
+        } else {
+            None
+        }
     };
+    layers.layers.push((
+        Source::CommandLine,
+        ProjectConfig {
+            exclude: values("exclude"),
+            include: values("include"),
+            no_buildtime: flag("no-buildtime", "buildtime"),
+            root: flag("root", "no-root"),
+            document_private_items: flag("document-private-items", "no-document-private-items"),
+            open: flag("open", "no-open"),
+        },
+    ));
+    if matches.is_present("show-config") {
+        print!("{}", layers);
+        return Ok(());
+    }
+    let config = layers.merged();
+    //Checked after merging, so -d can also come with root from the settings
+    if config.document_private_items == Some(true) && config.root != Some(true) {
+        return Err(Error::Config(
+            "document-private-items requires root, set it or pass --root".to_string(),
+        ));
+    }
 
-    //Cargo root directory
-    let dir = match find_rootdir() {
-        Ok(path) => path.canonicalize().unwrap(),
-        Err(e) => return Err(e),
+    let excluded_crates: Vec<&str> = config.exclude.iter().map(String::as_str).collect();
+    let extra_crates: Vec<&str> = config.include.iter().map(String::as_str).collect();
+
+    let features = FeatureSelection {
+        features: matches
+            .values_of("features")
+            .into_iter()
+            .flat_map(|f| f.split([',', ' ']))
+            .filter(|f| !f.is_empty())
+            .collect(),
+        all_features: matches.is_present("all-features"),
+        no_default_features: matches.is_present("no-default-features"),
     };
 
-    let mut cargo_toml = String::new();
-    File::open(dir.join("Cargo.toml"))
-        .map_err(|e| format!("Couldn't open Cargo.toml: {}", e))?
-        .read_to_string(&mut cargo_toml)
-        .unwrap();
-
-    let manifest_lock = read_cargo_lock(&dir)?;
-    let manifest: CargoToml = toml::from_str(&cargo_toml)
-        .map_err(|e| format!("{:?} failed to parse: {}", dir.canonicalize().unwrap(), e))?;
-
-    let mut crate_operations = Vec::new(); //`cargo doc`'s -p argument doesn't work when used at the root
-                                           //of a workspace, so queue up operations
-    if let Some(ref workspace) = manifest.workspace {
-        for member in &workspace.members {
-            //Cargo doesn't care about the actual name of a workspaced crate, just it's path
-            let local_dir = dir.join(&member);
-
-            let mut local_manifest_contents = String::new();
-            File::open(local_dir.join("Cargo.toml"))
-                .map_err(|e| format!("Couldn't open {:?}: {}", dir.canonicalize().unwrap(), e))?
-                .read_to_string(&mut local_manifest_contents)
-                .unwrap();
-
-            let local_manifest = toml::from_str(&local_manifest_contents)
-                .map_err(|e| format!("{:?} failed to parse: {}", dir.canonicalize().unwrap(), e))?;
-
-            let gotten_crates = get_crates(
-                local_manifest,
-                &manifest_lock,
-                &excluded_crates,
-                &extra_crates,
-                !matches.is_present("no-buildtime"),
-            )?;
-            crate_operations.push((local_dir, gotten_crates)); //Queue up operation
-        }
-
-        //Just because this Cargo.toml designates a workspace, does not mean it does not describe a crate,
-        //so look for a root crate.
-        if manifest.dependencies.is_some() {
-            crate_operations.push((
-                dir,
-                get_crates(
-                    manifest,
-                    &manifest_lock,
-                    &excluded_crates,
-                    &extra_crates,
-                    !matches.is_present("no-buildtime"),
-                )?,
-            ));
+    let kinds = if matches.is_present("only-dev") {
+        DependencyKinds {
+            normal: false,
+            build: false,
+            dev: true,
         }
     } else {
-        //Sigular crate, only one operation
-        crate_operations.push((
-            dir,
-            get_crates(
-                manifest,
-                &manifest_lock,
-                &excluded_crates,
-                &extra_crates,
-                !matches.is_present("no-buildtime"),
-            )?,
-        ));
-    }
-
-    for (dir, operation) in &crate_operations {
-        //Build command
-        if operation.is_empty() {
-            eprintln!(
-                "cargo-makedocs: no crates to document for dir {}",
-                dir.to_string_lossy()
-            );
-            continue;
+        DependencyKinds {
+            normal: true,
+            build: config.no_buildtime != Some(true),
+            dev: matches.is_present("dev"),
         }
+    };
 
-        let mut command = Command::new("cargo");
-        command
-            .arg("doc")
-            .arg("--no-deps")
-            .args(&create_arguments(&operation));
-
-        if matches.is_present("document-private-items") {
-            command.arg("--document-private-items");
-        }
+    //Target specific dependencies are evaluated against the host unless another target is requested
+    let platform = Platform::query(matches.value_of("target"), matches.value_of("toolchain"))?;
 
-        //`cargo doc` does not support the `-p` argument when at the root of a workspace.
-        std::env::set_current_dir(dir).map_err(|e| {
-            format!(
-                "Couldn't switch dir to {}: {}",
-                dir.canonicalize().unwrap().to_string_lossy(),
-                e
-            )
-        })?;
+    let members = MemberSelection {
+        workspace: matches.is_present("workspace"),
+        packages: matches.values_of("package"),
+        excluded: matches.values_of("exclude-member"),
+    };
 
-        if matches.is_present("root") {
-            let mut pkg_id_command = Command::new("cargo");
-            pkg_id_command.arg("pkgid");
-            let pkg_id =
-                String::from_utf8_lossy(&pkg_id_command.output().unwrap().stdout).replace("\n", "");
-            command.arg("-p").arg(pkg_id);
-        }
+    let options = Options {
+        excluded_crates,
+        extra_crates,
+        kinds,
+        platform,
+        features,
+        depth: match matches.find("depth") {
+            Some(m) => value_t!(m, "depth", usize).unwrap_or_else(|e| e.exit()),
+            None => 0,
+        },
+        members,
+        resolver: match matches.value_of("resolver") {
+            Some("metadata") => Resolver::Metadata,
+            _ => Resolver::Manifest,
+        },
+        root: config.root == Some(true),
+        document_private_items: config.document_private_items == Some(true),
+        open: config.open == Some(true),
+        checked_patterns: matches
+            .values_of("exclude")
+            .into_iter()
+            .chain(matches.values_of("include"))
+            .collect(),
+        strict: matches.is_present("strict"),
+        cargo_args: matches.values_of("cargo-args"),
+        target: matches.value_of("target"),
+        target_dir: matches.value_of("target-dir").map(Path::new),
+        profile: matches.value_of("profile"),
+        toolchain: matches.value_of("toolchain"),
+        docs_rs: matches.is_present("docs-rs"),
+        fail_fast: matches.is_present("fail-fast"),
+    };
 
-        //Build documentation
-        command.spawn().unwrap().wait().unwrap();
+    if let Some(explain_matches) = matches.subcommand_matches("explain") {
+        let name = explain_matches.value_of("crate").unwrap(); //Required
+        print!("{}", explain(&manifest_path, &options, name)?);
+        return Ok(());
     }
 
-    //Open docs if requested. `cargo doc` doesn't allow --open with more than one -p argument, so
-    //it has to be run a second time for this.
-    if matches.is_present("open") && !crate_operations[0].1.is_empty() {
-        let mut command = Command::new("cargo");
-        command.arg("doc").arg("--no-deps").arg("--open");
-
-        if !matches.is_present("root") {
-            command.arg("-p").arg(&crate_operations[0].1[0]);
+    let plan = plan(&manifest_path, &options)?;
+    for warning in &plan.warnings {
+        eprintln!("cargo-makedocs: warning: {}", warning);
+    }
+    if matches.value_of("format") == Some("json") {
+        println!("{:#}", plan.to_json());
+        return Ok(());
+    }
+    if matches.is_present("dry-run") {
+        for command in commands(&plan)? {
+            println!("{}", command);
+        }
+        return Ok(());
+    }
+    execute(&plan)?;
+
+    for operation in &plan.operations {
+        for c in &operation.crates {
+            let path = operation.doc_path(c);
+            if !path.exists() {
+                eprintln!(
+                    "cargo-makedocs: warning: cargo doc succeeded, but {} for {} doesn't exist",
+                    path.to_string_lossy(),
+                    c.spec
+                );
+            } else if operation.target_dir.is_some() {
+                //Crates documented in a target dir of their own are easy to miss
+                eprintln!(
+                    "cargo-makedocs: {} ({}) is documented separately in {}",
+                    c.key,
+                    c.spec,
+                    path.to_string_lossy()
+                );
+            }
         }
-
-        let dir = &crate_operations[0].0;
-        std::env::set_current_dir(dir).map_err(|e| {
-            format!(
-                "Couldn't switch dir to {}: {}",
-                dir.canonicalize().unwrap().to_string_lossy(),
-                e
-            )
-        })?;
-
-        command.spawn().unwrap().wait().unwrap();
     }
     Ok(())
 }
@@ -356,120 +219,228 @@ fn main() {
             .author(env!("CARGO_PKG_AUTHORS"))
             .arg(
                 Arg::with_name("exclude")
+                    .global(true)
                     .short("e")
                     .takes_value(true)
                     .multiple(true)
+                    .number_of_values(1)
                     .help("do not build documentation for a crate"),
             ).arg(
                 Arg::with_name("include")
+                    .global(true)
                     .short("i")
                     .takes_value(true)
                     .multiple(true)
+                    .number_of_values(1)
                     .help("build documentation for a crate"),
             ).arg(
                 Arg::with_name("open")
+                    .global(true)
                     .short("o")
                     .long("open")
                     .help("opens the built documentation")
+            ).arg(
+                Arg::with_name("no-open")
+                    .global(true)
+                    .long("no-open")
+                    .conflicts_with("open")
+                    .help("don't open the documentation, even if the settings say so")
             ).arg(
                 Arg::with_name("root")
+                    .global(true)
                     .short("r")
                     .long("root")
                     .help("Build the documentation for the root crate. When running in the root of a workspace, document each crate in the workspace as well.")
+            ).arg(
+                Arg::with_name("no-root")
+                    .global(true)
+                    .long("no-root")
+                    .conflicts_with("root")
+                    .help("Don't build the documentation for the root crate, even if the settings say so")
             ).arg(
                 Arg::with_name("document-private-items")
+                    .global(true)
                     .short("d")
                     .long("document-private-items")
                     .help("passes --document-private-items when building the docs for the root crate")
-                    .requires("root")
+            ).arg(
+                Arg::with_name("no-document-private-items")
+                    .global(true)
+                    .long("no-document-private-items")
+                    .conflicts_with("document-private-items")
+                    .help("don't pass --document-private-items, even if the settings say so")
             ).arg(
                 Arg::with_name("no-buildtime")
+                  .global(true)
                   .short("n")
                   .long("no-buildtime")
                   .help("Ignore buildtime dependencies")
+            ).arg(
+                Arg::with_name("buildtime")
+                  .global(true)
+                  .long("buildtime")
+                  .conflicts_with("no-buildtime")
+                  .help("Document buildtime dependencies, even if the settings ignore them")
+            ).arg(
+                Arg::with_name("dev")
+                  .global(true)
+                  .long("dev")
+                  .help("Also document dev-dependencies")
+            ).arg(
+                Arg::with_name("only-dev")
+                  .global(true)
+                  .long("only-dev")
+                  .help("Only document dev-dependencies")
+                  .conflicts_with_all(&["dev", "no-buildtime", "buildtime"])
+            ).arg(
+                Arg::with_name("features")
+                  .global(true)
+                  .long("features")
+                  .takes_value(true)
+                  .multiple(true)
+                  .number_of_values(1)
+                  .value_name("FEATURES")
+                  .help("Space or comma separated list of features to activate. Optional dependencies are only documented when an active feature enables them")
+            ).arg(
+                Arg::with_name("all-features")
+                  .global(true)
+                  .long("all-features")
+                  .help("Activate all available features")
+            ).arg(
+                Arg::with_name("no-default-features")
+                  .global(true)
+                  .long("no-default-features")
+                  .help("Do not activate the `default` feature")
+            ).arg(
+                Arg::with_name("depth")
+                  .global(true)
+                  .long("depth")
+                  .takes_value(true)
+                  .value_name("N")
+                  .help("Also document the dependencies of the dependencies, up to N levels down")
+            ).arg(
+                Arg::with_name("workspace")
+                  .global(true)
+                  .long("workspace")
+                  .help("Document the dependencies of every workspace member, not just the default members")
+            ).arg(
+                Arg::with_name("package")
+                  .global(true)
+                  .short("p")
+                  .long("package")
+                  .takes_value(true)
+                  .multiple(true)
+                  .number_of_values(1)
+                  .value_name("MEMBER")
+                  .help("Only document the dependencies of this workspace member, given by package name or path")
+            ).arg(
+                Arg::with_name("exclude-member")
+                  .global(true)
+                  .long("exclude-member")
+                  .takes_value(true)
+                  .multiple(true)
+                  .number_of_values(1)
+                  .value_name("MEMBER")
+                  .help("Don't document the dependencies of this workspace member, given by package name or path")
+            ).arg(
+                Arg::with_name("resolver")
+                  .global(true)
+                  .long("resolver")
+                  .takes_value(true)
+                  .possible_values(&["manifest", "metadata"])
+                  .default_value("manifest")
+                  .help("How to find the dependencies: by reading Cargo.toml and Cargo.lock, or from the dependency graph resolved by `cargo metadata`. Falls back to reading Cargo.toml if `cargo metadata` fails")
+            ).arg(
+                Arg::with_name("target")
+                  .global(true)
+                  .long("target")
+                  .takes_value(true)
+                  .value_name("TRIPLE")
+                  .help("Document for this target triple instead of the host, including the target specific dependencies that apply to it")
+            ).arg(
+                Arg::with_name("target-dir")
+                  .global(true)
+                  .long("target-dir")
+                  .takes_value(true)
+                  .value_name("DIR")
+                  .help("Directory for all generated artifacts, passed on to cargo")
+            ).arg(
+                Arg::with_name("profile")
+                  .global(true)
+                  .long("profile")
+                  .takes_value(true)
+                  .value_name("PROFILE-NAME")
+                  .help("Build the docs with the given profile, passed on to cargo")
+            ).arg(
+                Arg::with_name("toolchain")
+                  .global(true)
+                  .long("toolchain")
+                  .takes_value(true)
+                  .value_name("TOOLCHAIN")
+                  .help("Run cargo with this rustup toolchain, like `cargo +nightly`")
+            ).arg(
+                Arg::with_name("docs-rs")
+                  .global(true)
+                  .long("docs-rs")
+                  .help("Document crates with the features and rustdoc and rustc arguments from their [package.metadata.docs.rs], each in its own target dir")
+            ).arg(
+                Arg::with_name("keep-going")
+                  .global(true)
+                  .long("keep-going")
+                  .help("Run every cargo doc command even if one fails, then report the failures. This is the default")
+            ).arg(
+                Arg::with_name("fail-fast")
+                  .global(true)
+                  .long("fail-fast")
+                  .conflicts_with("keep-going")
+                  .help("Stop at the first cargo doc command that fails")
+            ).arg(
+                Arg::with_name("strict")
+                  .global(true)
+                  .long("strict")
+                  .help("Fail if a crate passed with -e or -i matches no dependency or crate in Cargo.lock, instead of warning")
+            ).arg(
+                Arg::with_name("format")
+                  .global(true)
+                  .long("format")
+                  .takes_value(true)
+                  .possible_values(&["json"])
+                  .help("Print the crates that would be documented, why, and the ones excluded, instead of documenting them")
+            ).arg(
+                Arg::with_name("dry-run")
+                  .global(true)
+                  .long("dry-run")
+                  .help("Print the cargo commands that would be run in each directory instead of running them")
+            ).arg(
+                Arg::with_name("show-config")
+                  .global(true)
+                  .long("show-config")
+                  .help("Print the settings from the config files, environment and command line, and where each comes from, then exit")
+            ).arg(
+                Arg::with_name("cargo-args")
+                  .multiple(true)
+                  .last(true)
+                  .value_name("CARGO_DOC_ARGS")
+                  .help("Arguments passed on to every `cargo doc` run, like --offline, --locked or -j 4")
+            ).subcommand(
+                SubCommand::with_name("explain")
+                  .about("Explain why a crate is or isn't documented with the given options")
+                  .arg(
+                      Arg::with_name("crate")
+                        .required(true)
+                        .help("The crate, by package name or by the key it is declared with")
+                  )
             )
         )
         .get_matches();
 
     let matches = matches.subcommand_matches("makedocs").unwrap(); //Cannot panic when run through cargo
 
-    match run(matches) {
+    match run(&Args::new(matches)) {
         Ok(()) => (),
         Err(e) => {
             eprintln!("cargo-makedocs: {}", e);
-            exit(1)
+            exit(e.exit_code())
         }
     }
 }
-
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn get_crates_buildtime_deps() {
-        use super::get_crates;
-        let cargo_toml = r#"dependencies = {renamed = {package = "foo", version = "1.3"}}"#;
-        let cargo_lock = r#"[[package]]
-name="foo"
-version="1.3.5""#;
-        let manifest = toml::from_str(cargo_toml).unwrap();
-        let manifest_lock = toml::from_str(cargo_lock).unwrap();
-        let crates = get_crates(manifest, &manifest_lock, &[], &[], true).unwrap();
-        assert_eq!(crates, ["foo:1.3.5"]);
-    }
-    #[test]
-    fn get_crates_include_exclude_crate() {
-        use super::get_crates;
-        let cargo_toml = r#"dependencies = {some-crate = "1.0.0", foo = "1.2.0"}"#;
-        let cargo_lock = r#"[[package]]
-name = "some-crate"
-version="1.3.2"
-[[package]]
-name="foo"
-version="1.3.5"
-[[package]]
-name = "include-me"
-version="1.2.3""#;
-        let manifest = toml::from_str(cargo_toml).unwrap();
-        let manifest_lock = toml::from_str(cargo_lock).unwrap();
-        let crates = get_crates(
-            manifest,
-            &manifest_lock,
-            &["some-crate"],
-            &["include-me"],
-            true,
-        )
-        .unwrap();
-        assert_eq!(crates, ["foo:1.3.5", "include-me"]);
-    }
-
-    #[test]
-    fn get_crates_from_path() {
-        use super::get_crates;
-        let cargo_toml = r#"dependencies = {some-crate = { path = "some-crate" }}"#;
-        let cargo_lock = r#"[[package]]
-name = "some-crate"
-version="1.3.2"
-[[package]]
-name = "some-crate"
-version = "1.3.6""#;
-        let manifest = toml::from_str(cargo_toml).unwrap();
-        let manifest_lock = toml::from_str(cargo_lock).unwrap();
-        let crates = get_crates(manifest, &manifest_lock, &[], &[], true).unwrap();
-        assert_eq!(crates, ["some-crate:1.3.6"]);
-    }
-
-    #[test]
-    fn get_version_from_git() {
-        use super::get_crates;
-        let cargo_toml = r#"dependencies = {libc = { git = "https://github.com/rust-lang/libc" }}"#;
-        let cargo_lock = r#"[[package]]
-name = "libc"
-version = "0.2.43"
-source = "git+https://github.com/rust-lang/libc#9c5e70ae306463a23ec02179ac2c9fe05c3fb44e"
-"#;
-        let manifest = toml::from_str(cargo_toml).unwrap();
-        let manifest_lock = toml::from_str(cargo_lock).unwrap();
-        let crates = get_crates(manifest, &manifest_lock, &[], &[], true).unwrap();
-        assert_eq!(crates, ["libc:0.2.43"]);
-    }
-}
@@ -0,0 +1,445 @@
+//Finds the crates to document from the dependency graph resolved by `cargo metadata`, instead of
+//reading Cargo.toml and Cargo.lock by hand. Cargo already knows about every manifest feature, so
+//this keeps working where the hand-written parsing falls short.
+use super::{
+    cargo_command, select_members, DependencyKind, DocCrate, ExcludedCrate, Member, Options,
+    Reason, Resolution, Selection,
+};
+use crate::config::{self, ProjectConfig};
+use crate::error::Error;
+use crate::pattern::{self, CratePattern};
+use semver::Version;
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    workspace_members: Vec<String>,
+    //Only reported by cargo 1.71 and later
+    workspace_default_members: Option<Vec<String>>,
+    resolve: Option<Resolve>,
+    workspace_root: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    id: String,
+    name: String,
+    version: String,
+    //Not set for path dependencies
+    source: Option<String>,
+    manifest_path: PathBuf,
+    dependencies: Vec<Dependency>,
+    //The `[package.metadata]` table
+    metadata: Option<config::Metadata>,
+}
+
+#[derive(Deserialize)]
+struct Dependency {
+    name: String,
+    rename: Option<String>,
+    req: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Resolve {
+    nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    id: String,
+    deps: Vec<NodeDep>,
+}
+
+#[derive(Deserialize)]
+struct NodeDep {
+    //The name the dependency is imported as, with dashes replaced by underscores
+    name: String,
+    pkg: String,
+    dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Deserialize)]
+struct DepKind {
+    kind: Option<String>,
+    //The `[target]` table the dependency is declared in
+    target: Option<String>,
+}
+
+impl Dependency {
+    fn key(&self) -> &String {
+        self.rename.as_ref().unwrap_or(&self.name)
+    }
+}
+
+impl Package {
+    fn dir(&self) -> &Path {
+        self.manifest_path.parent().unwrap()
+    }
+
+    fn config(&self) -> ProjectConfig {
+        self.metadata
+            .as_ref()
+            .and_then(|m| m.makedocs.clone())
+            .unwrap_or_default()
+    }
+
+    //Since cargo 1.77 package IDs are valid package ID specs, older versions need name:version
+    fn spec(&self) -> String {
+        if self.id.contains('#') {
+            self.id.clone()
+        } else {
+            format!("{}:{}", self.name, self.version)
+        }
+    }
+}
+
+fn query(dir: &Path, options: &Options) -> Result<Metadata, Error> {
+    let output = cargo_command(options.toolchain)
+        .current_dir(dir)
+        .args(["metadata", "--format-version", "1"])
+        .args(["--filter-platform", &options.platform.triple])
+        .args(options.features.cargo_args())
+        .output()
+        .map_err(|e| Error::Cargo(format!("Couldn't run cargo metadata: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::Cargo(format!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    serde_json::from_slice(&output.stdout).map_err(|e| {
+        Error::Cargo(format!(
+            "Couldn't parse the output of cargo metadata: {}",
+            e
+        ))
+    })
+}
+
+pub fn crate_operations(dir: &Path, options: &Options) -> Result<Resolution, Error> {
+    let metadata = query(dir, options)?;
+    Ok(Resolution {
+        operations: resolve_operations(&metadata, dir, options)?,
+        known_crates: known_crates(&metadata),
+    })
+}
+
+//Every package in the graph, and the keys dependencies are renamed to
+fn known_crates(metadata: &Metadata) -> Vec<(String, Option<Version>)> {
+    let packages = metadata
+        .packages
+        .iter()
+        .map(|p| (p.name.clone(), Version::parse(&p.version).ok()));
+    let renames = metadata
+        .packages
+        .iter()
+        .flat_map(|p| &p.dependencies)
+        .filter_map(|d| Some((d.rename.clone()?, None)));
+    packages.chain(renames).collect()
+}
+
+fn resolve_operations(
+    metadata: &Metadata,
+    dir: &Path,
+    options: &Options,
+) -> Result<Vec<(PathBuf, Selection)>, Error> {
+    let resolve = metadata.resolve.as_ref().ok_or_else(|| {
+        Error::Cargo("cargo metadata didn't resolve any dependencies".to_string())
+    })?;
+    let package = |id: &str| metadata.packages.iter().find(|p| p.id == id);
+
+    let members: Vec<&Package> = metadata
+        .workspace_members
+        .iter()
+        .filter_map(|id| package(id))
+        .collect();
+    //Only document the crate in dir when not at the root of the workspace, like when reading
+    //Cargo.toml
+    let dirs = if dir == metadata.workspace_root {
+        let all: Vec<Member> = members
+            .iter()
+            .map(|p| Member {
+                dir: p.dir().to_path_buf(),
+                name: Some(p.name.clone()),
+            })
+            .collect();
+        let default_members = metadata.workspace_default_members.as_ref().map(|ids| {
+            ids.iter()
+                .filter_map(|id| package(id))
+                .map(|p| p.dir().to_path_buf())
+                .collect()
+        });
+        select_members(dir, &all, default_members, &options.members)?
+    } else {
+        vec![dir.to_path_buf()]
+    };
+
+    let mut operations = Vec::new();
+    for member_dir in dirs {
+        let member = members
+            .iter()
+            .find(|p| p.dir() == member_dir)
+            .ok_or_else(|| {
+                Error::Cargo(format!(
+                    "cargo metadata doesn't list a package in {:?}",
+                    member_dir
+                ))
+            })?;
+        let node = resolve
+            .nodes
+            .iter()
+            .find(|n| n.id == member.id)
+            .ok_or_else(|| {
+                Error::Cargo(format!("cargo metadata didn't resolve {}", member.name))
+            })?;
+
+        //The member's own settings apply to its dependencies on top of the options
+        let config = member.config();
+        let excluded_crates: Vec<&str> = options
+            .excluded_crates
+            .iter()
+            .copied()
+            .chain(config.exclude.iter().map(String::as_str))
+            .collect();
+        let excluded_crates = CratePattern::parse_all(&excluded_crates)?;
+
+        let mut crates = Vec::new();
+        let mut excluded = Vec::new();
+        let mut direct = Vec::new();
+        for dep in &node.deps {
+            let enabled = dep.dep_kinds.iter().find_map(|k| {
+                let kind = match k.kind.as_deref() {
+                    None if options.kinds.normal => DependencyKind::Normal,
+                    Some("build") if options.kinds.build => DependencyKind::Build,
+                    Some("dev") if options.kinds.dev => DependencyKind::Dev,
+                    _ => return None,
+                };
+                Some(Reason::Dependency {
+                    kind,
+                    target: k.target.clone(),
+                })
+            });
+            //Exclusions refer to the key the dependency is declared with in Cargo.toml
+            let declared = member
+                .dependencies
+                .iter()
+                .find(|d| d.key().replace('-', "_") == dep.name);
+            let key = declared.map_or(&dep.name, Dependency::key);
+            let (reason, p) = match (enabled, package(&dep.pkg)) {
+                (Some(reason), Some(p)) => (reason, p),
+                _ => continue,
+            };
+            let version = Version::parse(&p.version).ok();
+            if let Some(pattern) =
+                pattern::matching(&excluded_crates, &[key, &p.name], version.as_ref())
+            {
+                excluded.push(ExcludedCrate {
+                    key: key.clone(),
+                    name: p.name.clone(),
+                    version,
+                    pattern: pattern.text.to_string(),
+                });
+                continue;
+            }
+            let spec = p.spec();
+            if !crates.iter().any(|c: &DocCrate| c.spec == spec) {
+                crates.push(DocCrate {
+                    key: key.clone(),
+                    name: p.name.clone(),
+                    spec,
+                    requirement: declared.and_then(|d| d.req.clone()),
+                    version,
+                    source: p.source.clone(),
+                    manifest_dir: Some(p.dir().to_path_buf()),
+                    reason,
+                });
+                direct.push(p);
+            }
+        }
+
+        //Walk the resolved graph for transitive dependencies
+        let mut level = direct;
+        for depth in 1..=options.depth {
+            let mut next = Vec::new();
+            for parent in level {
+                let deps = resolve
+                    .nodes
+                    .iter()
+                    .filter(|n| n.id == parent.id)
+                    .flat_map(|n| &n.deps);
+                for p in deps.filter_map(|d| package(&d.pkg)) {
+                    let spec = p.spec();
+                    let version = Version::parse(&p.version).ok();
+                    if !pattern::matches_any(&excluded_crates, &[&p.name], version.as_ref())
+                        && !crates.iter().any(|c| c.spec == spec)
+                    {
+                        crates.push(DocCrate {
+                            key: p.name.clone(),
+                            name: p.name.clone(),
+                            spec,
+                            requirement: None,
+                            version,
+                            source: p.source.clone(),
+                            manifest_dir: Some(p.dir().to_path_buf()),
+                            reason: Reason::Transitive {
+                                parent: parent.name.clone(),
+                                depth,
+                            },
+                        });
+                        next.push(p);
+                    }
+                }
+            }
+            level = next;
+        }
+        let extra_crates: Vec<&str> = options
+            .extra_crates
+            .iter()
+            .copied()
+            .chain(config.include.iter().map(String::as_str))
+            .collect();
+        for pattern in CratePattern::parse_all(&extra_crates)? {
+            if let (Some(name), false) = (pattern.exact, pattern.has_version()) {
+                if !crates.iter().any(|c| c.spec == name) {
+                    crates.push(DocCrate::included(name, None));
+                }
+                continue;
+            }
+            //The latest matching version of each matching package
+            let mut matching: Vec<(&Package, Version)> = metadata
+                .packages
+                .iter()
+                .filter_map(|p| Some((p, Version::parse(&p.version).ok()?)))
+                .filter(|(p, v)| pattern.matches(&[&p.name], Some(v)))
+                .collect();
+            matching.sort_by(|(a, v), (b, w)| a.name.cmp(&b.name).then(w.cmp(v)));
+            matching.dedup_by(|(a, _), (b, _)| a.name == b.name);
+            for (p, version) in matching {
+                let spec = p.spec();
+                if !crates.iter().any(|c| c.spec == spec) {
+                    crates.push(DocCrate {
+                        spec,
+                        requirement: pattern.version_requirement().map(str::to_string),
+                        source: p.source.clone(),
+                        manifest_dir: Some(p.dir().to_path_buf()),
+                        ..DocCrate::included(&p.name, Some(version))
+                    });
+                }
+            }
+        }
+        operations.push((
+            member_dir,
+            Selection {
+                crates,
+                excluded,
+                warnings: Vec::new(),
+            },
+        ));
+    }
+    Ok(operations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_operations, Metadata};
+    use crate::tests::{options, specs};
+    use crate::{DependencyKind, Options, Reason};
+    use std::path::Path;
+
+    const METADATA: &str = r#"{
+  "packages": [
+    {"id": "path+file:///ws/app#0.1.0", "name": "app", "version": "0.1.0",
+     "manifest_path": "/ws/app/Cargo.toml",
+     "dependencies": [{"name": "futures", "rename": "futures01"}, {"name": "serde-json", "rename": null, "req": "^1.0"},
+                      {"name": "cc", "rename": null}, {"name": "proptest", "rename": null}]},
+    {"id": "path+file:///ws/tool#0.1.0", "name": "tool", "version": "0.1.0",
+     "manifest_path": "/ws/tool/Cargo.toml", "dependencies": []},
+    {"id": "registry+https://github.com/rust-lang/crates.io-index#futures@0.1.29", "name": "futures",
+     "version": "0.1.29", "manifest_path": "/registry/futures/Cargo.toml", "dependencies": []},
+    {"id": "serde-json 1.0.48 (registry+https://github.com/rust-lang/crates.io-index)", "name": "serde-json",
+     "version": "1.0.48", "source": "registry+https://github.com/rust-lang/crates.io-index", "manifest_path": "/registry/serde-json/Cargo.toml", "dependencies": []},
+    {"id": "registry+https://github.com/rust-lang/crates.io-index#cc@1.0.50", "name": "cc",
+     "version": "1.0.50", "manifest_path": "/registry/cc/Cargo.toml", "dependencies": []},
+    {"id": "registry+https://github.com/rust-lang/crates.io-index#proptest@0.9.5", "name": "proptest",
+     "version": "0.9.5", "manifest_path": "/registry/proptest/Cargo.toml", "dependencies": []}
+  ],
+  "workspace_members": ["path+file:///ws/app#0.1.0", "path+file:///ws/tool#0.1.0"],
+  "workspace_default_members": ["path+file:///ws/app#0.1.0"],
+  "resolve": {"nodes": [
+    {"id": "path+file:///ws/app#0.1.0", "deps": [
+      {"name": "futures01", "pkg": "registry+https://github.com/rust-lang/crates.io-index#futures@0.1.29",
+       "dep_kinds": [{"kind": null, "target": null}]},
+      {"name": "serde_json", "pkg": "serde-json 1.0.48 (registry+https://github.com/rust-lang/crates.io-index)",
+       "dep_kinds": [{"kind": null, "target": null}]},
+      {"name": "cc", "pkg": "registry+https://github.com/rust-lang/crates.io-index#cc@1.0.50",
+       "dep_kinds": [{"kind": "build", "target": null}]},
+      {"name": "proptest", "pkg": "registry+https://github.com/rust-lang/crates.io-index#proptest@0.9.5",
+       "dep_kinds": [{"kind": "dev", "target": null}]}
+    ]},
+    {"id": "serde-json 1.0.48 (registry+https://github.com/rust-lang/crates.io-index)", "deps": [
+      {"name": "proptest", "pkg": "registry+https://github.com/rust-lang/crates.io-index#proptest@0.9.5",
+       "dep_kinds": [{"kind": null, "target": null}]}
+    ]},
+    {"id": "path+file:///ws/tool#0.1.0", "deps": []}
+  ]},
+  "workspace_root": "/ws"
+}"#;
+
+    #[test]
+    fn resolve_operations_from_metadata() {
+        let metadata: Metadata = serde_json::from_str(METADATA).unwrap();
+        let options = Options {
+            excluded_crates: vec!["futures01"],
+            extra_crates: vec!["extra"],
+            depth: 1,
+            ..options()
+        };
+
+        let operations = resolve_operations(&metadata, Path::new("/ws"), &options).unwrap();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].0, Path::new("/ws/app"));
+        assert_eq!(
+            specs(&operations[0].1.crates),
+            [
+                "serde-json:1.0.48",
+                "registry+https://github.com/rust-lang/crates.io-index#cc@1.0.50",
+                "registry+https://github.com/rust-lang/crates.io-index#proptest@0.9.5",
+                "extra"
+            ]
+        );
+        let serde_json = &operations[0].1.crates[0];
+        assert_eq!(serde_json.requirement.as_deref(), Some("^1.0"));
+        assert_eq!(
+            serde_json.source.as_deref(),
+            Some("registry+https://github.com/rust-lang/crates.io-index")
+        );
+        let excluded = &operations[0].1.excluded;
+        assert_eq!(excluded.len(), 1);
+        assert_eq!(
+            (excluded[0].key.as_str(), excluded[0].name.as_str()),
+            ("futures01", "futures")
+        );
+        let reasons: Vec<&Reason> = operations[0].1.crates.iter().map(|c| &c.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                &Reason::Dependency {
+                    kind: DependencyKind::Normal,
+                    target: None
+                },
+                &Reason::Dependency {
+                    kind: DependencyKind::Build,
+                    target: None
+                },
+                &Reason::Transitive {
+                    parent: "serde-json".to_string(),
+                    depth: 1
+                },
+                &Reason::Included
+            ]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//The crates given with -e and -i. The name is a glob pattern like `*-sys` or `tokio-*`, optionally
+//followed by a version after an @, like `serde@1.0.190` or `rand@0.7`. Like with cargo's package
+//ID specs, a partial version matches every version starting with it.
+use crate::error::Error;
+use glob::Pattern;
+use semver::{Version, VersionReq};
+
+#[derive(Debug)]
+pub(crate) struct CratePattern<'a> {
+    pub text: &'a str,
+    name: Pattern,
+    //The name when the pattern isn't a glob
+    pub exact: Option<&'a str>,
+    //The requirement as written, and parsed
+    version: Option<(String, VersionReq)>,
+}
+
+impl<'a> CratePattern<'a> {
+    pub fn parse(text: &'a str) -> Result<CratePattern<'a>, Error> {
+        let (name, version) = match text.find('@') {
+            Some(i) => (&text[..i], Some(&text[i + 1..])),
+            None => (text, None),
+        };
+        let version = match version {
+            Some(v) => {
+                let requirement = format!("={}", v);
+                let parsed = VersionReq::parse(&requirement).map_err(|e| {
+                    Error::VersionReq(format!("invalid version {} in {}: {}", v, text, e))
+                })?;
+                Some((requirement, parsed))
+            }
+            None => None,
+        };
+        let pattern = Pattern::new(name)
+            .map_err(|e| Error::Config(format!("invalid crate pattern {}: {}", text, e)))?;
+        Ok(CratePattern {
+            text,
+            exact: Some(name).filter(|n| Pattern::escape(n) == *n),
+            name: pattern,
+            version,
+        })
+    }
+
+    pub fn parse_all<'b, I: IntoIterator<Item = &'b &'a str>>(
+        patterns: I,
+    ) -> Result<Vec<CratePattern<'a>>, Error>
+    where
+        'a: 'b,
+    {
+        patterns
+            .into_iter()
+            .map(|p| CratePattern::parse(p))
+            .collect()
+    }
+
+    pub fn has_version(&self) -> bool {
+        self.version.is_some()
+    }
+
+    //The version requirement given after the @
+    pub fn version_requirement(&self) -> Option<&str> {
+        self.version.as_ref().map(|(r, _)| r.as_str())
+    }
+
+    //The version requirement to resolve the crate with
+    pub fn requirement(&self) -> &str {
+        self.version_requirement().unwrap_or("*")
+    }
+
+    pub fn matches_name(&self, name: &str) -> bool {
+        self.name.matches(name)
+    }
+
+    //Whether the crate with the given names, usually its key and package name, and version matches
+    pub fn matches(&self, names: &[&str], version: Option<&Version>) -> bool {
+        names.iter().any(|n| self.name.matches(n))
+            && match (&self.version, version) {
+                (Some((_, req)), Some(version)) => req.matches(version),
+                (Some(_), None) => false,
+                (None, _) => true,
+            }
+    }
+
+    //The message to show if this pattern, given with -e or -i as described by kind, matches none of
+    //the known crates
+    pub fn unmatched(&self, kind: &str, known: &[(String, Option<Version>)]) -> Option<String> {
+        if known.iter().any(|(n, v)| self.matches(&[n], v.as_ref())) {
+            return None;
+        }
+        let mut message = format!(
+            "{} pattern {} matches no dependency or crate in Cargo.lock",
+            kind, self.text
+        );
+        let suggestions = self.suggestions(known.iter().map(|(n, _)| n.as_str()));
+        if !suggestions.is_empty() {
+            message.push_str(", did you mean ");
+            message.push_str(&suggestions.join(" or "));
+            message.push('?');
+        }
+        Some(message)
+    }
+
+    //Up to three known names close to the name of the pattern, the closest first
+    fn suggestions<'b, I: Iterator<Item = &'b str>>(&self, known: I) -> Vec<&'b str> {
+        let name = self.name.as_str();
+        let max_distance = (name.len() / 3).max(1);
+        let mut close: Vec<(usize, &str)> = known
+            .map(|k| (strsim::levenshtein(name, k), k))
+            .filter(|(d, _)| *d <= max_distance)
+            .collect();
+        close.sort();
+        close.dedup_by(|a, b| a.1 == b.1);
+        close.into_iter().take(3).map(|(_, k)| k).collect()
+    }
+}
+
+pub(crate) fn matches_any(
+    patterns: &[CratePattern],
+    names: &[&str],
+    version: Option<&Version>,
+) -> bool {
+    matching(patterns, names, version).is_some()
+}
+
+//The first of patterns matching the crate
+pub(crate) fn matching<'p, 'a>(
+    patterns: &'p [CratePattern<'a>],
+    names: &[&str],
+    version: Option<&Version>,
+) -> Option<&'p CratePattern<'a>> {
+    patterns.iter().find(|p| p.matches(names, version))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn crate_patterns() {
+        use super::CratePattern;
+        use semver::Version;
+        let sys = CratePattern::parse("*-sys").unwrap();
+        assert_eq!(sys.exact, None);
+        assert!(sys.matches(&["openssl-sys"], None));
+        assert!(sys.matches(&["ssl", "openssl-sys"], None));
+        assert!(!sys.matches(&["openssl"], None));
+
+        let rand = CratePattern::parse("rand@0.7").unwrap();
+        assert_eq!(rand.exact, Some("rand"));
+        let version = |v| Version::parse(v).unwrap();
+        assert!(rand.matches(&["rand"], Some(&version("0.7.3"))));
+        assert!(!rand.matches(&["rand"], Some(&version("0.8.5"))));
+        assert!(!rand.matches(&["rand"], None));
+
+        assert!(CratePattern::parse("rand@abc").is_err());
+        assert!(CratePattern::parse("[rand").is_err());
+    }
+
+    #[test]
+    fn unmatched_patterns() {
+        use super::CratePattern;
+        use semver::Version;
+        let known = vec![
+            (
+                "serde".to_string(),
+                Some(Version::parse("1.0.190").unwrap()),
+            ),
+            ("serde_json".to_string(), None),
+            (
+                "tokio-util".to_string(),
+                Some(Version::parse("0.7.8").unwrap()),
+            ),
+        ];
+        let unmatched = |p| CratePattern::parse(p).unwrap().unmatched("exclude", &known);
+        assert_eq!(unmatched("serde"), None);
+        assert_eq!(unmatched("serde*"), None);
+        assert_eq!(unmatched("serde@1"), None);
+        assert_eq!(
+            unmatched("serd").unwrap(),
+            "exclude pattern serd matches no dependency or crate in Cargo.lock, did you mean serde?"
+        );
+        assert_eq!(
+            unmatched("tokio-utl").unwrap(),
+            "exclude pattern tokio-utl matches no dependency or crate in Cargo.lock, did you mean tokio-util?"
+        );
+        assert_eq!(
+            unmatched("serde@2").unwrap(),
+            "exclude pattern serde@2 matches no dependency or crate in Cargo.lock, did you mean serde?"
+        );
+        assert_eq!(
+            unmatched("winapi").unwrap(),
+            "exclude pattern winapi matches no dependency or crate in Cargo.lock"
+        );
+    }
+}